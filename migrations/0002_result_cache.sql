@@ -0,0 +1,10 @@
+-- Results of running code on the playground or Godbolt, see src/cache.rs
+CREATE TABLE IF NOT EXISTS result_cache (
+    -- Hex-encoded SHA-256 digest
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    -- Unix timestamp
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS result_cache_expires_at ON result_cache (expires_at);
@@ -0,0 +1,16 @@
+-- Code commands that users ran, see src/commands/history.rs
+CREATE TABLE IF NOT EXISTS command_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    command TEXT NOT NULL,
+    -- Flags as space-separated `key=value` pairs
+    flags TEXT NOT NULL,
+    code TEXT NOT NULL,
+    success BOOLEAN NOT NULL,
+    -- First line of the output
+    summary TEXT NOT NULL,
+    -- Unix timestamp
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS command_history_user_id ON command_history (user_id, id DESC);
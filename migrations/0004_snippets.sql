@@ -0,0 +1,18 @@
+-- Named code snippets, see src/commands/snippet.rs
+CREATE TABLE IF NOT EXISTS snippets (
+    id BIGSERIAL PRIMARY KEY,
+    -- 0 for the shared snippets curated by the moderators
+    owner_id BIGINT NOT NULL,
+    name TEXT NOT NULL,
+    -- The playground command that runs the snippet
+    command TEXT NOT NULL,
+    -- Default flags as space-separated `key=value` pairs
+    flags TEXT NOT NULL,
+    code TEXT NOT NULL,
+    public BOOLEAN NOT NULL,
+    -- Unix timestamp
+    updated_at BIGINT NOT NULL,
+    UNIQUE (owner_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS snippets_name ON snippets (name);
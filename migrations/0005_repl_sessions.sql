@@ -0,0 +1,10 @@
+-- Definitions accumulated by ?repl in a channel, see src/commands/playground/repl.rs
+CREATE TABLE IF NOT EXISTS repl_sessions (
+    channel_id BIGINT PRIMARY KEY,
+    -- Crate attributes like `#![feature(...)]`, one per line
+    attributes TEXT NOT NULL,
+    -- Items like structs, functions, impls and uses, as the users wrote them
+    items TEXT NOT NULL,
+    -- Unix timestamp of the last ?repl invocation
+    updated_at BIGINT NOT NULL
+);
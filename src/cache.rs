@@ -0,0 +1,264 @@
+//! Caches the results of running code on the external services
+//!
+//! The same snippets are run over and over again, for example when a message with a command is
+//! edited, or with popular examples. Results are kept in an in-memory LRU, backed by Postgres so
+//! that they survive restarts. Results of the nightly toolchain expire sooner, since nightly
+//! changes every day.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use shuttle_runtime::SecretStore;
+use tracing::warn;
+
+use crate::scheduler::{wait_for_turn, Service};
+use crate::types::Context;
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+	/// How many results are kept in memory
+	pub capacity: usize,
+	/// How long results are kept
+	pub ttl: Duration,
+	/// How long results of nightly toolchains are kept
+	pub nightly_ttl: Duration,
+}
+
+impl CacheConfig {
+	pub fn from_secrets(secret_store: &SecretStore) -> Result<Self, Error> {
+		let number = |key: &str, default: u64| -> Result<u64, Error> {
+			Ok(secret_store
+				.get(key)
+				.map(|s| s.parse::<u64>())
+				.transpose()?
+				.unwrap_or(default))
+		};
+
+		Ok(Self {
+			capacity: usize::try_from(number("CACHE_CAPACITY", 500)?)?,
+			ttl: Duration::from_secs(number("CACHE_TTL", 60 * 60 * 24)?),
+			nightly_ttl: Duration::from_secs(number("CACHE_NIGHTLY_TTL", 60 * 60)?),
+		})
+	}
+}
+
+/// Results that can be cached
+pub trait Cacheable: Serialize + DeserializeOwned {
+	/// Whether the result is worth caching. Running the code again may give a different result
+	/// after a timeout, for example
+	fn is_cacheable(&self) -> bool {
+		true
+	}
+}
+
+/// Identifies a result by a SHA-256 digest of the command, the code and the flags. The digest is
+/// stable across Rust releases, unlike `std`'s hashers, so the results stored in Postgres stay
+/// valid, and it's long enough that two inputs won't share a key
+#[derive(Debug, Clone)]
+pub struct CacheKey {
+	/// Hex-encoded digest
+	digest: String,
+	nightly: bool,
+}
+
+impl CacheKey {
+	/// `flags` must contain everything besides the code that influences the result
+	pub fn new(command: &str, code: &str, flags: &str, nightly: bool) -> Self {
+		// Whitespace at the end of lines and blank lines around the code don't change the result
+		let code = code
+			.lines()
+			.map(str::trim_end)
+			.collect::<Vec<_>>()
+			.join("\n");
+
+		let mut hasher = Sha256::new();
+		for part in [command, code.trim_matches('\n'), flags] {
+			// The length separates the parts unambiguously
+			hasher.update((part.len() as u64).to_le_bytes());
+			hasher.update(part);
+		}
+		Self {
+			digest: format!("{:x}", hasher.finalize()),
+			nightly,
+		}
+	}
+}
+
+#[derive(Debug)]
+struct MemoryEntry {
+	value: String,
+	/// Unix timestamp
+	expires_at: i64,
+	last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+	entries: HashMap<String, MemoryEntry>,
+	/// Incremented on every access, to find the least recently used entry
+	clock: u64,
+}
+
+impl Lru {
+	fn get(&mut self, key: &str, now: i64) -> Option<String> {
+		self.clock += 1;
+		let entry = self.entries.get_mut(key)?;
+		if entry.expires_at <= now {
+			self.entries.remove(key);
+			return None;
+		}
+		entry.last_used = self.clock;
+		Some(entry.value.clone())
+	}
+
+	fn insert(&mut self, key: &str, value: String, expires_at: i64, capacity: usize) {
+		self.clock += 1;
+		if !self.entries.contains_key(key) && self.entries.len() >= capacity {
+			let least_recently_used = self
+				.entries
+				.iter()
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(key, _)| key.clone());
+			if let Some(least_recently_used) = least_recently_used {
+				self.entries.remove(&least_recently_used);
+			}
+		}
+		self.entries.insert(
+			key.to_owned(),
+			MemoryEntry {
+				value,
+				expires_at,
+				last_used: self.clock,
+			},
+		);
+	}
+}
+
+#[must_use]
+pub fn unix_now() -> i64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |d| d.as_secs() as i64)
+}
+
+#[derive(Debug)]
+pub struct ResultCache {
+	config: CacheConfig,
+	database: sqlx::PgPool,
+	memory: Mutex<Lru>,
+}
+
+impl ResultCache {
+	#[must_use]
+	pub fn new(config: CacheConfig, database: sqlx::PgPool) -> Self {
+		Self {
+			config,
+			database,
+			memory: Mutex::default(),
+		}
+	}
+
+	async fn load(&self, key: &CacheKey) -> Option<String> {
+		let now = unix_now();
+		if let Some(value) = self.memory.lock().unwrap().get(&key.digest, now) {
+			return Some(value);
+		}
+
+		let row = sqlx::query_as::<_, (String, i64)>(
+			"SELECT value, expires_at FROM result_cache WHERE key = $1 AND expires_at > $2",
+		)
+		.bind(&key.digest)
+		.bind(now)
+		.fetch_optional(&self.database)
+		.await;
+		match row {
+			Ok(Some((value, expires_at))) => {
+				self.memory.lock().unwrap().insert(
+					&key.digest,
+					value.clone(),
+					expires_at,
+					self.config.capacity,
+				);
+				Some(value)
+			}
+			Ok(None) => None,
+			Err(e) => {
+				warn!("failed to load cached result: {}", e);
+				None
+			}
+		}
+	}
+
+	async fn store(&self, key: &CacheKey, value: String) {
+		let now = unix_now();
+		let ttl = if key.nightly {
+			self.config.nightly_ttl
+		} else {
+			self.config.ttl
+		};
+		let expires_at = now + ttl.as_secs() as i64;
+		self.memory.lock().unwrap().insert(
+			&key.digest,
+			value.clone(),
+			expires_at,
+			self.config.capacity,
+		);
+
+		let result = async {
+			sqlx::query("DELETE FROM result_cache WHERE expires_at <= $1")
+				.bind(now)
+				.execute(&self.database)
+				.await?;
+			sqlx::query(
+				"INSERT INTO result_cache (key, value, expires_at) VALUES ($1, $2, $3)
+				ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at",
+			)
+			.bind(&key.digest)
+			.bind(value)
+			.bind(expires_at)
+			.execute(&self.database)
+			.await?;
+			Ok::<_, Error>(())
+		};
+		if let Err(e) = result.await {
+			warn!("failed to store result in cache: {}", e);
+		}
+	}
+}
+
+/// Returns the cached result for `key`, or waits for a free slot of `service` and runs `run`. The
+/// cache is bypassed if `key` is `None`
+pub async fn cached<T: Cacheable>(
+	ctx: Context<'_>,
+	key: Option<CacheKey>,
+	service: Service,
+	run: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+	let cache = &ctx.data().cache;
+	if let Some(key) = &key {
+		if let Some(value) = cache.load(key).await {
+			match serde_json::from_str(&value) {
+				Ok(value) => return Ok(value),
+				Err(e) => warn!("failed to deserialize cached result: {}", e),
+			}
+		}
+	}
+
+	let permit = wait_for_turn(ctx, service).await?;
+	let value = run.await?;
+	drop(permit);
+
+	if let Some(key) = &key {
+		if value.is_cacheable() {
+			match serde_json::to_string(&value) {
+				Ok(json) => cache.store(key, json).await,
+				Err(e) => warn!("failed to serialize result for the cache: {}", e),
+			}
+		}
+	}
+	Ok(value)
+}
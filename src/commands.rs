@@ -1,9 +1,12 @@
 pub use godbolt::*;
 pub use playground::*;
 
+pub mod code_actions;
 pub mod crates;
 pub mod godbolt;
+pub mod history;
 pub mod modmail;
 pub mod playground;
+pub mod snippet;
 pub mod thread_pin;
 pub mod utilities;
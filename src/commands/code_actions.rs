@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{bail, Error};
+use poise::serenity_prelude as serenity;
+use serenity::Mentionable;
+
+use crate::commands::godbolt::godbolt_message;
+use crate::commands::playground::{
+	parse_flags, result_message, run_clippy, run_expand, run_fmt, run_miri, run_play_or_eval,
+	ResultHandling,
+};
+use crate::helpers::attached_code;
+use crate::types::Context;
+
+/// The actions offered in the select menu, as value and label
+const ACTIONS: [(&str, &str); 7] = [
+	("play", "Run (?play)"),
+	("eval", "Evaluate expression (?eval)"),
+	("miri", "Check for undefined behavior (?miri)"),
+	("clippy", "Lint (?clippy)"),
+	("fmt", "Format (?fmt)"),
+	("expand", "Expand macros (?expand)"),
+	("godbolt", "Show assembly (?godbolt)"),
+];
+
+/// Returns the contents of all code blocks with three backticks in the message
+fn extract_code_blocks(mut content: &str) -> Vec<(&str, &str)> {
+	let mut code_blocks = Vec::new();
+	while let Some(start) = content.find("```") {
+		content = &content[(start + 3)..];
+		let Some(end) = content.find("```") else {
+			break;
+		};
+		let code_block = &content[..end];
+		content = &content[(end + 3)..];
+
+		// The language tag can only be on the first line, and only if there are more lines
+		let (language, code) = match code_block.split_once('\n') {
+			Some((first_line, rest)) if !first_line.trim().contains(' ') => {
+				(first_line.trim(), rest)
+			}
+			_ => ("", code_block),
+		};
+		code_blocks.push((language, code));
+	}
+	code_blocks
+}
+
+/// Finds the code in a message. The first Rust code block is preferred over other code blocks,
+/// which may contain compiler output and the like. Without code blocks, an attached source file
+/// is used
+async fn find_code(message: &serenity::Message) -> Result<Option<String>, Error> {
+	let code_blocks = extract_code_blocks(&message.content);
+	let code_block = code_blocks
+		.iter()
+		.find(|(language, _)| matches!(*language, "" | "rs" | "rust"))
+		.or(code_blocks.first());
+
+	match code_block {
+		Some((_, code)) if !code.trim().is_empty() => Ok(Some((*code).to_owned())),
+		_ => attached_code(message, 0).await,
+	}
+}
+
+/// Runs one of the playground or godbolt commands on a message's code. To use, right-click the
+/// message, then go to "Apps" > "Code actions".
+#[poise::command(
+	ephemeral,
+	context_menu_command = "Code actions",
+	hide_in_help,
+	category = "Playground"
+)]
+pub async fn code_actions(
+	ctx: Context<'_>,
+	#[description = "Message with the code to run"] message: serenity::Message,
+) -> Result<(), Error> {
+	let Some(code) = find_code(&message).await? else {
+		bail!("This message doesn't contain a code block or an attached .rs or .txt file");
+	};
+
+	let custom_id = format!("{}-action", ctx.id());
+	let options = ACTIONS
+		.iter()
+		.map(|&(value, label)| serenity::CreateSelectMenuOption::new(label, value))
+		.collect();
+	let handle = ctx
+		.send(
+			poise::CreateReply::default()
+				.content("What do you want to do with this code?")
+				.components(vec![serenity::CreateActionRow::SelectMenu(
+					serenity::CreateSelectMenu::new(
+						&custom_id,
+						serenity::CreateSelectMenuKind::String { options },
+					)
+					.placeholder("Choose an action"),
+				)]),
+		)
+		.await?;
+
+	let author_id = ctx.author().id;
+	let Some(interaction) = handle
+		.message()
+		.await?
+		.await_component_interaction(ctx)
+		.filter(move |mci: &serenity::ComponentInteraction| {
+			mci.data.custom_id == custom_id && mci.user.id == author_id
+		})
+		.timeout(Duration::from_mins(2))
+		.await
+	else {
+		handle
+			.edit(
+				ctx,
+				poise::CreateReply::default()
+					.content("No action was chosen in time")
+					.components(vec![]),
+			)
+			.await?;
+		return Ok(());
+	};
+
+	let action = match &interaction.data.kind {
+		serenity::ComponentInteractionDataKind::StringSelect { values } => {
+			values.first().cloned().unwrap_or_default()
+		}
+		_ => String::new(),
+	};
+	let Some(&(_, label)) = ACTIONS.iter().find(|(value, _)| *value == action) else {
+		bail!("unknown code action `{}`", action);
+	};
+	interaction
+		.create_response(
+			ctx,
+			serenity::CreateInteractionResponse::UpdateMessage(
+				serenity::CreateInteractionResponseMessage::new()
+					.content(format!("_Running {label}..._"))
+					.components(vec![]),
+			),
+		)
+		.await?;
+
+	let header = format!("{} used {label}:\n", ctx.author().mention());
+	let (flags, _) = parse_flags(poise::KeyValueArgs(HashMap::new()));
+	let reply = match action.as_str() {
+		"play" => {
+			let (_, result) = run_play_or_eval(ctx, &flags, &code, ResultHandling::None).await?;
+			result_message(&result, &header)
+		}
+		"eval" => {
+			let (_, result) = run_play_or_eval(ctx, &flags, &code, ResultHandling::Print).await?;
+			result_message(&result, &header)
+		}
+		"miri" => result_message(&run_miri(ctx, &flags, &code).await?.1, &header),
+		"clippy" => result_message(&run_clippy(ctx, &flags, &code).await?.1, &header),
+		"fmt" => result_message(&run_fmt(ctx, &flags, &code).await?.1, &header),
+		"expand" => result_message(&run_expand(ctx, &flags, &code).await?.1, &header),
+		_ => godbolt_message(ctx, &code, &header).await?,
+	};
+
+	let posted = message
+		.channel_id
+		.send_message(
+			ctx,
+			reply
+				.reference_message(&message)
+				// Neither the invoker nor the author of the message should be pinged
+				.allowed_mentions(serenity::CreateAllowedMentions::new()),
+		)
+		.await?;
+
+	handle
+		.edit(
+			ctx,
+			poise::CreateReply::default().content(format!("Done: {}", posted.link())),
+		)
+		.await?;
+	Ok(())
+}
@@ -1,21 +1,31 @@
 use std::{collections::HashMap, mem::take};
 
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, bail, Error};
+use poise::serenity_prelude as serenity;
 use poise::{CodeBlockError, KeyValueArgs};
 use tracing::warn;
 
-use crate::types::Context;
+use crate::cache::{cached, CacheKey, Cacheable};
+use crate::helpers::{
+	code_block_or_attachment, code_from_modal, long_output_message, send_long_output, LongOutput,
+	OutputMode,
+};
+use crate::scheduler::Service;
+use crate::types::{ApplicationContext, Context};
 
 mod targets;
 pub use targets::*;
 
 const LLVM_MCA_TOOL_ID: &str = "llvm-mcatrunk";
 
+#[derive(serde::Serialize, serde::Deserialize)]
 struct Compilation {
 	output: String,
 	stderr: String,
 }
 
+impl Cacheable for Compilation {}
+
 #[derive(Debug, serde::Deserialize)]
 struct GodboltOutputSegment {
 	text: String,
@@ -113,7 +123,30 @@ async fn compile_rust_source(
 	})
 }
 
-async fn save_to_shortlink(http: &reqwest::Client, req: &GodboltRequest<'_>) -> String {
+/// Like [`compile_rust_source`], but returns a cached result if there is one
+async fn compile_cached(
+	ctx: Context<'_>,
+	request: &GodboltRequest<'_>,
+	use_cache: bool,
+) -> Result<Compilation, Error> {
+	let key = use_cache.then(|| {
+		let flags = format!(
+			"{} {} {}",
+			request.rustc, request.flags, request.run_llvm_mca
+		);
+		let nightly = request.rustc.contains("nightly");
+		CacheKey::new("godbolt", request.source_code, &flags, nightly)
+	});
+	cached(
+		ctx,
+		key,
+		Service::Godbolt,
+		compile_rust_source(&ctx.data().http, request),
+	)
+	.await
+}
+
+async fn save_to_shortlink(http: &reqwest::Client, req: &GodboltRequest<'_>) -> Option<String> {
 	#[derive(serde::Deserialize)]
 	struct GodboltShortenerResponse {
 		url: String,
@@ -154,20 +187,29 @@ async fn save_to_shortlink(http: &reqwest::Client, req: &GodboltRequest<'_>) ->
 				.url,
 		)
 	};
-	url.await.unwrap_or_else(|e| {
-		warn!("failed to generate godbolt shortlink: {}", e);
-		"failed to retrieve".to_owned()
-	})
+	url.await
+		.map_err(|e| warn!("failed to generate godbolt shortlink: {}", e))
+		.ok()
 }
 
 #[derive(PartialEq, Clone, Copy)]
-#[allow(unused)]
 enum GodboltMode {
 	Asm,
 	LlvmIr,
 	Mca,
 }
 
+impl GodboltMode {
+	/// Name of the command, as recorded in the command history
+	fn command(self) -> &'static str {
+		match self {
+			GodboltMode::Asm => "godbolt",
+			GodboltMode::LlvmIr => "llvmir",
+			GodboltMode::Mca => "mca",
+		}
+	}
+}
+
 fn note(code: &str) -> &'static str {
 	if code.contains("#[no_mangle]") {
 		""
@@ -176,65 +218,81 @@ fn note(code: &str) -> &'static str {
 	}
 }
 
+fn godbolt_output<'a>(
+	godbolt_result: &'a Compilation,
+	lang: &'static str,
+	note: &'a str,
+) -> LongOutput<'a> {
+	const NO_OUTPUT: &str = "No output. Consider adding `#[no_mangle]` before your functions.";
+	let (blocks, note) = match (godbolt_result.output.trim(), godbolt_result.stderr.trim()) {
+		("", "") => (vec![("", " ")], NO_OUTPUT),
+		(output, "") => (vec![(lang, output)], note),
+		("<Compilation failed>", errors) => (vec![("ansi", errors)], "Compilation failed."),
+		("", warnings) => (vec![("ansi", warnings)], NO_OUTPUT),
+		(output, errors) => (vec![(lang, output), ("ansi", errors)], note),
+	};
+
+	LongOutput {
+		header: "",
+		blocks,
+		footer: note,
+	}
+}
+
 async fn respond_codeblocks(
 	ctx: Context<'_>,
 	godbolt_result: Compilation,
 	godbolt_request: GodboltRequest<'_>,
 	lang: &'static str,
 	note: &str,
+	output_mode: OutputMode,
 ) -> Result<(), Error> {
-	const NO_OUTPUT: &str = "No output. Consider adding `#[no_mangle]` before your functions.";
-	match (godbolt_result.output.trim(), godbolt_result.stderr.trim()) {
-		("", "") => respond_codeblock(ctx, "", " ", NO_OUTPUT, &godbolt_request).await?,
-		(output, "") => respond_codeblock(ctx, lang, output, note, &godbolt_request).await?,
-		("<Compilation failed>", errors) => {
-			respond_codeblock(ctx, "ansi", errors, "Compilation failed.", &godbolt_request).await?;
-		}
-		("", warnings) => {
-			respond_codeblock(ctx, "ansi", warnings, NO_OUTPUT, &godbolt_request).await?;
-		}
-		(output, errors) => {
-			ctx.say(
-				crate::helpers::trim_text(
-					&format!("```{lang}\n{output}``````ansi\n{errors}"),
-					&format!("\n```{note}"),
-					async {
-						format!(
-							"Output too large. Godbolt link: <{}>",
-							save_to_shortlink(&ctx.data().http, &godbolt_request).await,
-						)
-					},
-				)
-				.await,
-			)
-			.await?;
-		}
+	let output = godbolt_output(&godbolt_result, lang, note);
+	let truncation_msg = async {
+		save_to_shortlink(&ctx.data().http, &godbolt_request)
+			.await
+			.map(|url| format!("Output too large. Godbolt link: <{url}>"))
 	};
+	let mut reply =
+		send_long_output(ctx, &output, output_mode, truncation_msg, vec![], vec![]).await?;
+	// Without extra buttons, this only handles the page buttons until they time out
+	reply.next_interaction(ctx).await?;
 	Ok(())
 }
 
-async fn respond_codeblock(
+/// Compiles the code with the default settings of ?godbolt and formats the assembly as a
+/// standalone message, e.g. to reply to some other message than the command invocation
+pub async fn godbolt_message(
 	ctx: Context<'_>,
-	codeblock_lang: &str,
-	text: &str,
-	note: &str,
-	godbolt_request: &GodboltRequest<'_>,
-) -> Result<(), Error> {
-	ctx.say(
-		crate::helpers::trim_text(
-			&format!("```{codeblock_lang}\n{text}"),
-			&format!("\n```{note}"),
-			async {
-				format!(
-					"Output too large. Godbolt link: <{}>",
-					save_to_shortlink(&ctx.data().http, godbolt_request).await,
-				)
-			},
-		)
-		.await,
-	)
-	.await?;
-	Ok(())
+	code: &str,
+	header: &str,
+) -> Result<serenity::CreateMessage, Error> {
+	let (rustc, flags) = rustc_id_and_flags(ctx.data(), &KeyValueArgs(HashMap::new())).await?;
+	let godbolt_request = GodboltRequest {
+		source_code: code,
+		rustc: &rustc,
+		flags: &flags,
+		run_llvm_mca: false,
+	};
+	let godbolt_result = compile_cached(ctx, &godbolt_request, true).await?;
+
+	let mut output = godbolt_output(&godbolt_result, "x86asm", note(code));
+	output.header = header;
+	Ok(long_output_message(&output))
+}
+
+/// Creates a Godbolt shortlink for the code with the default settings of ?godbolt
+pub async fn godbolt_link(ctx: Context<'_>, code: &str) -> Result<String, Error> {
+	let (rustc, flags) = rustc_id_and_flags(ctx.data(), &KeyValueArgs(HashMap::new())).await?;
+	let godbolt_request = GodboltRequest {
+		source_code: code,
+		rustc: &rustc,
+		flags: &flags,
+		run_llvm_mca: false,
+	};
+	save_to_shortlink(&ctx.data().http, &godbolt_request)
+		.await
+		.ok_or(anyhow!("Failed to create a Godbolt link"))
 }
 
 fn parse(args: &str) -> Result<(KeyValueArgs, String), CodeBlockError> {
@@ -283,6 +341,107 @@ fn parse(args: &str) -> Result<(KeyValueArgs, String), CodeBlockError> {
 	Ok((KeyValueArgs(map), code))
 }
 
+/// The `output` argument is for the bot, so it must not be passed on to rustc
+fn output_mode(params: &mut KeyValueArgs) -> Result<OutputMode, Error> {
+	Ok(params
+		.0
+		.remove("output")
+		.map(|mode| mode.parse::<OutputMode>())
+		.transpose()?
+		.unwrap_or_default())
+}
+
+/// The `cache` argument is for the bot as well
+fn use_cache(params: &mut KeyValueArgs) -> Result<bool, Error> {
+	Ok(params
+		.0
+		.remove("cache")
+		.map(|use_cache| use_cache.parse::<bool>())
+		.transpose()?
+		.unwrap_or(true))
+}
+
+/// Like [`parse`], but takes the code from an attached file if there's no code block
+async fn parse_arguments(ctx: Context<'_>, args: &str) -> Result<(KeyValueArgs, String), Error> {
+	if args.contains('`') {
+		return Ok(parse(args)?);
+	}
+
+	// Without a code block, nothing terminates the last flag, so add a separator
+	let (mut params, _) = parse(&format!("{} ", args.trim()))?;
+	params.0.remove("");
+	let code = code_block_or_attachment(ctx, None, 0).await?;
+	Ok((params, code))
+}
+
+// All the godbolt commands work the same, apart from the flags and how the output is displayed
+async fn run_godbolt(
+	ctx: Context<'_>,
+	mut params: KeyValueArgs,
+	code: &str,
+	mode: GodboltMode,
+) -> Result<(), Error> {
+	let output_mode = output_mode(&mut params)?;
+	let use_cache = use_cache(&mut params)?;
+	let (rustc, mut flags) = rustc_id_and_flags(ctx.data(), &params).await?;
+	if mode == GodboltMode::LlvmIr {
+		flags += " --emit=llvm-ir -Cdebuginfo=0";
+	}
+	let godbolt_request = GodboltRequest {
+		source_code: code,
+		rustc: &rustc,
+		flags: &flags,
+		run_llvm_mca: mode == GodboltMode::Mca,
+	};
+	let godbolt_result = compile_cached(ctx, &godbolt_request, use_cache).await?;
+
+	crate::commands::history::record(
+		ctx,
+		mode.command(),
+		&crate::commands::history::flags_to_string(&params),
+		code,
+		godbolt_result.output.trim() != "<Compilation failed>",
+		if godbolt_result.stderr.trim().is_empty() {
+			&godbolt_result.output
+		} else {
+			&godbolt_result.stderr
+		},
+	)
+	.await;
+
+	let lang = match mode {
+		GodboltMode::Asm => "x86asm",
+		GodboltMode::LlvmIr => "llvm",
+		GodboltMode::Mca => "rust",
+	};
+	respond_codeblocks(
+		ctx,
+		godbolt_result,
+		godbolt_request,
+		lang,
+		note(code),
+		output_mode,
+	)
+	.await
+}
+
+/// Runs `code` again with the Godbolt command called `command`, e.g. to repeat an entry of the
+/// command history
+pub async fn rerun_godbolt(
+	ctx: Context<'_>,
+	command: &str,
+	params: KeyValueArgs,
+	code: &str,
+) -> Result<(), Error> {
+	let mode = match command {
+		"godbolt" => GodboltMode::Asm,
+		"llvmir" => GodboltMode::LlvmIr,
+		"mca" => GodboltMode::Mca,
+		_ => bail!("`{}` is not a Godbolt command", command),
+	};
+	run_godbolt(ctx, params, code, mode).await
+}
+
 /// View assembly using Godbolt
 ///
 /// Compile Rust code using <https://rust.godbolt.org>. Full optimizations are applied unless \
@@ -297,20 +456,12 @@ fn parse(args: &str) -> Result<(KeyValueArgs, String), CodeBlockError> {
 /// Optional arguments:
 /// - `flags*`: flags to pass to rustc invocation. Defaults to ["-Copt-level=3", "--edition=2024"]
 /// - `rustc`: compiler version to invoke. Defaults to `nightly`. Possible values: `nightly`, `beta` or full version like `1.45.2`
+/// - `output`: how to show output that doesn't fit into a message. Defaults to `auto`. Possible values: `auto`, `pages`, `file`, `truncate`
+/// - `cache`: whether to reuse an earlier result for the same code and flags. Defaults to `true`
 #[poise::command(prefix_command, category = "Godbolt", broadcast_typing, track_edits)]
-pub async fn godbolt(ctx: Context<'_>, #[rest] arguments: String) -> Result<(), Error> {
-	let (params, code) = parse(&arguments)?;
-	let (rustc, flags) = rustc_id_and_flags(ctx.data(), &params).await?;
-	let godbolt_request = GodboltRequest {
-		source_code: &code,
-		rustc: &rustc,
-		flags: &flags,
-		run_llvm_mca: false,
-	};
-	let godbolt_result = compile_rust_source(&ctx.data().http, &godbolt_request).await?;
-
-	let note = note(&code);
-	respond_codeblocks(ctx, godbolt_result, godbolt_request, "x86asm", note).await
+pub async fn godbolt(ctx: Context<'_>, #[rest] arguments: Option<String>) -> Result<(), Error> {
+	let (params, code) = parse_arguments(ctx, arguments.as_deref().unwrap_or_default()).await?;
+	run_godbolt(ctx, params, &code, GodboltMode::Asm).await
 }
 
 /// Run performance analysis using llvm-mca
@@ -327,21 +478,12 @@ pub async fn godbolt(ctx: Context<'_>, #[rest] arguments: String) -> Result<(),
 /// Optional arguments:
 /// - `flags*`: flags to pass to rustc invocation. Defaults to ["-Copt-level=3", "--edition=2024"]
 /// - `rustc`: compiler version to invoke. Defaults to `nightly`. Possible values: `nightly`, `beta` or full version like `1.45.2`
+/// - `output`: how to show output that doesn't fit into a message. Defaults to `auto`. Possible values: `auto`, `pages`, `file`, `truncate`
+/// - `cache`: whether to reuse an earlier result for the same code and flags. Defaults to `true`
 #[poise::command(prefix_command, category = "Godbolt", broadcast_typing, track_edits)]
-pub async fn mca(ctx: Context<'_>, #[rest] arguments: String) -> Result<(), Error> {
-	let (params, code) = parse(&arguments)?;
-	let (rustc, flags) = rustc_id_and_flags(ctx.data(), &params).await?;
-	let godbolt_request = GodboltRequest {
-		source_code: &code,
-		rustc: &rustc,
-		flags: &flags,
-		run_llvm_mca: true,
-	};
-
-	let godbolt_result = compile_rust_source(&ctx.data().http, &godbolt_request).await?;
-
-	let note = note(&code);
-	respond_codeblocks(ctx, godbolt_result, godbolt_request, "rust", note).await
+pub async fn mca(ctx: Context<'_>, #[rest] arguments: Option<String>) -> Result<(), Error> {
+	let (params, code) = parse_arguments(ctx, arguments.as_deref().unwrap_or_default()).await?;
+	run_godbolt(ctx, params, &code, GodboltMode::Mca).await
 }
 
 /// View LLVM IR using Godbolt
@@ -360,18 +502,72 @@ pub async fn mca(ctx: Context<'_>, #[rest] arguments: String) -> Result<(), Erro
 /// Optional arguments:
 /// - `flags*`: flags to pass to rustc invocation. Defaults to ["-Copt-level=3", "--edition=2024"]
 /// - `rustc`: compiler version to invoke. Defaults to `nightly`. Possible values: `nightly`, `beta` or full version like `1.45.2`
+/// - `output`: how to show output that doesn't fit into a message. Defaults to `auto`. Possible values: `auto`, `pages`, `file`, `truncate`
+/// - `cache`: whether to reuse an earlier result for the same code and flags. Defaults to `true`
 #[poise::command(prefix_command, category = "Godbolt", broadcast_typing, track_edits)]
-pub async fn llvmir(ctx: Context<'_>, #[rest] arguments: String) -> Result<(), Error> {
-	let (params, code) = parse(&arguments)?;
-	let (rustc, flags) = rustc_id_and_flags(ctx.data(), &params).await?;
-	let godbolt_request = GodboltRequest {
-		source_code: &code,
-		rustc: &rustc,
-		flags: &(flags + " --emit=llvm-ir -Cdebuginfo=0"),
-		run_llvm_mca: false,
+pub async fn llvmir(ctx: Context<'_>, #[rest] arguments: Option<String>) -> Result<(), Error> {
+	let (params, code) = parse_arguments(ctx, arguments.as_deref().unwrap_or_default()).await?;
+	run_godbolt(ctx, params, &code, GodboltMode::LlvmIr).await
+}
+
+// The slash command variants ask for the code in a modal, and take the flags as a single string
+async fn run_godbolt_slash(
+	ctx: ApplicationContext<'_>,
+	rustc: Option<String>,
+	flags: Option<String>,
+	mode: GodboltMode,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
 	};
-	let godbolt_result = compile_rust_source(&ctx.data().http, &godbolt_request).await?;
 
-	let note = note(&code);
-	respond_codeblocks(ctx, godbolt_result, godbolt_request, "llvm", note).await
+	let (mut params, _) = parse(&format!("{} ", flags.as_deref().unwrap_or_default().trim()))?;
+	params.0.remove("");
+	if let Some(rustc) = rustc {
+		params.0.insert("rustc".to_owned(), rustc);
+	}
+
+	run_godbolt(Context::Application(ctx), params, &code, mode).await
+}
+
+/// View assembly using Godbolt
+#[poise::command(slash_command, rename = "godbolt", category = "Godbolt")]
+pub async fn godbolt_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Compiler version, like `nightly`, `beta` or `1.45.2`"]
+	#[autocomplete = "autocomplete_rustc"]
+	rustc: Option<String>,
+	#[description = "Flags to pass to rustc, like `-Copt-level=2 --edition=2021`"] flags: Option<
+		String,
+	>,
+) -> Result<(), Error> {
+	run_godbolt_slash(ctx, rustc, flags, GodboltMode::Asm).await
+}
+
+/// Run performance analysis using llvm-mca
+#[poise::command(slash_command, rename = "mca", category = "Godbolt")]
+pub async fn mca_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Compiler version, like `nightly`, `beta` or `1.45.2`"]
+	#[autocomplete = "autocomplete_rustc"]
+	rustc: Option<String>,
+	#[description = "Flags to pass to rustc, like `-Copt-level=2 --edition=2021`"] flags: Option<
+		String,
+	>,
+) -> Result<(), Error> {
+	run_godbolt_slash(ctx, rustc, flags, GodboltMode::Mca).await
+}
+
+/// View LLVM IR using Godbolt
+#[poise::command(slash_command, rename = "llvmir", category = "Godbolt")]
+pub async fn llvmir_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Compiler version, like `nightly`, `beta` or `1.45.2`"]
+	#[autocomplete = "autocomplete_rustc"]
+	rustc: Option<String>,
+	#[description = "Flags to pass to rustc, like `-Copt-level=2 --edition=2021`"] flags: Option<
+		String,
+	>,
+) -> Result<(), Error> {
+	run_godbolt_slash(ctx, rustc, flags, GodboltMode::LlvmIr).await
 }
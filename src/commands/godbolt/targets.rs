@@ -128,6 +128,22 @@ pub async fn fetch_godbolt_metadata(
 	data.godbolt_metadata.lock().unwrap()
 }
 
+pub(crate) async fn autocomplete_rustc(
+	ctx: Context<'_>,
+	partial: &str,
+) -> impl Iterator<Item = String> {
+	let partial = partial.to_ascii_lowercase();
+	fetch_godbolt_metadata(ctx.data())
+		.await
+		.targets
+		.iter()
+		.map(|target| target.semver.clone())
+		.filter(|semver| semver.contains(&partial))
+		.take(25)
+		.collect::<Vec<_>>()
+		.into_iter()
+}
+
 // Generates godbolt-compatible rustc identifier and flags from command input
 //
 // Transforms human readable rustc version (e.g. "1.34.1") into compiler id on godbolt (e.g. "r1341")
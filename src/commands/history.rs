@@ -0,0 +1,202 @@
+//! Remembers the code that users ran, so that they can run it again later with different flags
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Error};
+use poise::serenity_prelude as serenity;
+use tracing::warn;
+
+use crate::cache::unix_now;
+use crate::types::Context;
+
+/// How many invocations are kept per user
+const HISTORY_LENGTH: i64 = 50;
+/// How many invocations ?history shows
+const HISTORY_PAGE: i64 = 10;
+/// Maximum length of the result summary, in characters
+const SUMMARY_LENGTH: usize = 80;
+
+const GODBOLT_COMMANDS: [&str; 3] = ["godbolt", "llvmir", "mca"];
+
+/// First non-empty line of the output, shortened to fit into a single line of ?history
+fn summarize(output: &str) -> String {
+	let line = output
+		.lines()
+		.map(str::trim)
+		.find(|line| !line.is_empty())
+		.unwrap_or_default();
+	if line.chars().count() > SUMMARY_LENGTH {
+		line.chars().take(SUMMARY_LENGTH - 1).collect::<String>() + "…"
+	} else {
+		line.to_owned()
+	}
+}
+
+/// Formats flags as space-separated `key=value` pairs, the way they are stored
+#[must_use]
+pub fn flags_to_string(flags: &poise::KeyValueArgs) -> String {
+	let mut pairs = flags
+		.0
+		.iter()
+		.map(|(key, value)| format!("{key}={value}"))
+		.collect::<Vec<_>>();
+	pairs.sort();
+	pairs.join(" ")
+}
+
+/// Parses stored flags and overrides them with `overrides`
+#[must_use]
+pub fn merge_flags(stored: &str, overrides: poise::KeyValueArgs) -> poise::KeyValueArgs {
+	let mut flags = poise::KeyValueArgs(
+		stored
+			.split_whitespace()
+			.map(|flag| match flag.split_once('=') {
+				Some((key, value)) => (key.to_owned(), value.to_owned()),
+				None => (flag.to_owned(), String::new()),
+			})
+			.collect(),
+	);
+	flags.0.extend(overrides.0);
+	flags
+}
+
+/// Stores an invocation of `command` in the history of the command invoker. `flags` are the
+/// flags as `key=value` pairs, `code` the code as the user wrote it
+///
+/// Errors are only logged, since the history is not essential for running code.
+pub async fn record(
+	ctx: Context<'_>,
+	command: &str,
+	flags: &str,
+	code: &str,
+	success: bool,
+	output: &str,
+) {
+	let database = &ctx.data().database;
+	let user_id = ctx.author().id.get() as i64;
+
+	let result = async {
+		sqlx::query(
+			"INSERT INTO command_history (user_id, command, flags, code, success, summary, created_at)
+			VALUES ($1, $2, $3, $4, $5, $6, $7)",
+		)
+		.bind(user_id)
+		.bind(command)
+		.bind(flags)
+		.bind(code)
+		.bind(success)
+		.bind(summarize(output))
+		.bind(unix_now())
+		.execute(database)
+		.await?;
+		sqlx::query(
+			"DELETE FROM command_history WHERE user_id = $1 AND id NOT IN (
+				SELECT id FROM command_history WHERE user_id = $1 ORDER BY id DESC LIMIT $2
+			)",
+		)
+		.bind(user_id)
+		.bind(HISTORY_LENGTH)
+		.execute(database)
+		.await?;
+		Ok::<_, Error>(())
+	};
+	if let Err(e) = result.await {
+		warn!("failed to record command history: {}", e);
+	}
+}
+
+/// Show the code you ran recently
+///
+/// Lists your most recent code commands. Run one of them again with `?rerun <number>`.
+#[poise::command(prefix_command, slash_command, category = "Playground", track_edits)]
+pub async fn history(ctx: Context<'_>) -> Result<(), Error> {
+	let entries = sqlx::query_as::<_, (String, String, bool, String, i64)>(
+		"SELECT command, flags, success, summary, created_at FROM command_history
+		WHERE user_id = $1 ORDER BY id DESC LIMIT $2",
+	)
+	.bind(ctx.author().id.get() as i64)
+	.bind(HISTORY_PAGE)
+	.fetch_all(&ctx.data().database)
+	.await?;
+
+	if entries.is_empty() {
+		ctx.say("You haven't run any code yet").await?;
+		return Ok(());
+	}
+
+	let mut text = String::new();
+	for (i, (command, flags, success, summary, created_at)) in entries.iter().enumerate() {
+		let _ = write!(text, "{}. **{}**", i + 1, command);
+		if !flags.is_empty() {
+			let _ = write!(text, " `{flags}`");
+		}
+		let _ = write!(
+			text,
+			" <t:{}:R> {}",
+			created_at,
+			if *success { "✅" } else { "❌" }
+		);
+		if !summary.is_empty() {
+			let _ = write!(text, " `{}`", summary.replace('`', "'"));
+		}
+		text += "\n";
+	}
+	text += "Run an entry again with `?rerun <number>`, optionally with different flags, like \
+		`?rerun 1 edition=2021`";
+
+	// The history may contain code that the user doesn't want to share. Slash command replies can
+	// be ephemeral, prefix command replies go to the user's DMs instead
+	if let Context::Application(_) = ctx {
+		ctx.send(poise::CreateReply::default().content(text).ephemeral(true))
+			.await?;
+	} else if ctx
+		.author()
+		.dm(ctx, serenity::CreateMessage::new().content(text))
+		.await
+		.is_ok()
+	{
+		ctx.say("Sent you your history in a DM").await?;
+	} else {
+		ctx.say("Couldn't DM you your history. Allow DMs from this server or use `/history`")
+			.await?;
+	}
+	Ok(())
+}
+
+/// Run code from your history again
+///
+/// Runs the code of an entry of `?history` again. Flags given here override the flags of the \
+/// original invocation.
+/// ```
+/// ?rerun 1 edition=2021 mode=release
+/// ```
+#[poise::command(prefix_command, category = "Playground", track_edits)]
+pub async fn rerun(
+	ctx: Context<'_>,
+	#[description = "Number of the entry in ?history"] index: usize,
+	flags: poise::KeyValueArgs,
+) -> Result<(), Error> {
+	if index == 0 {
+		bail!("History entries are numbered starting at 1");
+	}
+
+	let entry = sqlx::query_as::<_, (String, String, String)>(
+		"SELECT command, flags, code FROM command_history
+		WHERE user_id = $1 ORDER BY id DESC LIMIT 1 OFFSET $2",
+	)
+	.bind(ctx.author().id.get() as i64)
+	.bind(index as i64 - 1)
+	.fetch_optional(&ctx.data().database)
+	.await?;
+	let Some((command, stored_flags, code)) = entry else {
+		bail!("There's no entry {} in your history, see ?history", index);
+	};
+
+	let merged_flags = merge_flags(&stored_flags, flags);
+
+	if GODBOLT_COMMANDS.contains(&command.as_str()) {
+		crate::commands::godbolt::rerun_godbolt(ctx, &command, merged_flags, &code).await
+	} else {
+		crate::commands::playground::rerun_playground(ctx, &command, merged_flags, &code).await
+	}
+}
@@ -1,13 +1,30 @@
 //! run rust code on the rust-lang playground
 
+pub use api::{backend_from_secrets, PlaygroundBackend};
+pub use compile::*;
 pub use microbench::*;
 pub use misc_commands::*;
 pub use play_eval::*;
+pub use playall::*;
 pub use procmacro::*;
+pub use repl::*;
+pub use rerun::rerun_playground;
+pub use slash::*;
+pub use test::*;
+pub use util::{parse_flags, result_message, ResultHandling};
 
 mod api;
+mod compile;
+mod flags;
+mod lints;
 mod microbench;
 mod misc_commands;
 mod play_eval;
+mod playall;
 mod procmacro;
+mod repl;
+mod rerun;
+mod sandbox;
+mod slash;
+mod test;
 mod util;
@@ -1,19 +1,75 @@
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Error};
+use futures_util::{SinkExt as _, StreamExt as _};
 use reqwest::header;
-use serde::{Deserialize, Deserializer, Serialize};
-use tracing::info;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use shuttle_runtime::SecretStore;
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{info, warn};
 
 use crate::types::Context;
 
+use super::sandbox::{LocalSandbox, SandboxConfig};
+
+/// The flags of a playground command, see [`super::flags::FLAGS`]
+#[allow(clippy::struct_excessive_bools)] // Each bool is an independent on/off flag
+#[derive(Clone)]
 pub struct CommandFlags {
 	pub channel: Channel,
 	pub mode: Mode,
 	pub edition: Edition,
+	/// `None` leaves the crate type up to the command
+	pub crate_type: Option<CrateType>,
+	/// Architecture to compile for in ?asm, ?ir and ?mir
+	pub target: Architecture,
 	pub warn: bool,
 	pub run: bool,
+	/// Run the tests instead of `main`
+	pub tests: bool,
+	/// Set `RUST_BACKTRACE=1`
+	pub backtrace: bool,
+	pub asm_flavor: AssemblyFlavour,
+	pub demangle: bool,
+	pub filter: bool,
+	/// Run on both the stable and the nightly channel, for ?microbench
+	pub both_channels: bool,
+	/// Render ?microbench results as a bar chart
+	pub chart: bool,
+	/// Show the usage code of ?procmacro after macro expansion
+	pub expand: bool,
+	pub runtime: Runtime,
+	/// Print every top-level expression in ?eval
+	pub repl: bool,
+	pub across: Across,
+	pub aliasing: AliasingModel,
+	/// Forbid integer-to-pointer casts in Miri
+	pub strict_provenance: bool,
+	/// Make Miri check alignment only by the type, not by the address
+	pub symbolic_alignment: bool,
+	/// Seed of the random number generator of Miri
+	pub seed: u64,
+	/// Number of consecutive seeds, starting at `seed`, that ?miri runs with
+	pub seeds: u32,
+	/// Lint groups like `pedantic` and single lints that ?clippy enables, without `clippy::`
+	pub lints: Vec<String>,
+	pub output: crate::helpers::OutputMode,
+	pub cache: bool,
+}
+
+impl CommandFlags {
+	/// The flags that can influence the result of running code, for the result cache
+	pub fn cache_key(&self) -> String {
+		super::flags::FLAGS
+			.iter()
+			.filter(|flag| flag.affects_result)
+			.map(|flag| format!("{}={}", flag.name, flag.get(self)))
+			.collect::<Vec<_>>()
+			.join(" ")
+	}
 }
 
 #[derive(Debug, Serialize)]
@@ -25,16 +81,49 @@ pub struct PlaygroundRequest<'a> {
 	pub crate_type: CrateType,
 	pub mode: Mode,
 	pub tests: bool,
+	pub backtrace: bool,
 }
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MiriRequest<'a> {
 	pub edition: Edition,
 	pub code: &'a str,
+	pub aliasing_model: AliasingModel,
+	/// These aren't supported by play.rust-lang.org, only by the local sandbox
+	#[serde(skip)]
+	pub strict_provenance: bool,
+	#[serde(skip)]
+	pub symbolic_alignment: bool,
+	#[serde(skip)]
+	pub seed: u64,
 }
 
-// has the same fields
-pub type MacroExpansionRequest<'a> = MiriRequest<'a>;
+impl MiriRequest<'_> {
+	/// The options as `MIRIFLAGS`
+	pub fn miri_flags(&self) -> String {
+		let mut flags = Vec::new();
+		if let AliasingModel::Tree = self.aliasing_model {
+			flags.push("-Zmiri-tree-borrows".to_owned());
+		}
+		if self.strict_provenance {
+			flags.push("-Zmiri-strict-provenance".to_owned());
+		}
+		if self.symbolic_alignment {
+			flags.push("-Zmiri-symbolic-alignment-check".to_owned());
+		}
+		if self.seed != 0 {
+			flags.push(format!("-Zmiri-seed={}", self.seed));
+		}
+		flags.join(" ")
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct MacroExpansionRequest<'a> {
+	pub edition: Edition,
+	pub code: &'a str,
+}
 
 #[derive(Debug, Serialize)]
 pub struct ClippyRequest<'a> {
@@ -70,44 +159,81 @@ pub struct CompileRequest<'a> {
 	pub mode: Mode,
 	pub process_assembly: ProcessAssembly,
 	pub target: CompileTarget,
+	/// Not supported by play.rust-lang.org, only by the local sandbox
+	#[serde(skip)]
+	pub architecture: Architecture,
 	pub tests: bool,
 }
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum AssemblyFlavour {
 	#[default]
 	Intel,
-	#[allow(dead_code)]
 	Att,
 }
 
-#[derive(Debug, Default, Serialize)]
+impl FromStr for AssemblyFlavour {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"intel" => Ok(AssemblyFlavour::Intel),
+			"att" => Ok(AssemblyFlavour::Att),
+			_ => bail!("invalid assembly flavor `{}`", s),
+		}
+	}
+}
+
+impl AssemblyFlavour {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			AssemblyFlavour::Intel => "intel",
+			AssemblyFlavour::Att => "att",
+		}
+	}
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DemangleAssembly {
 	#[default]
 	Demangle,
-	#[allow(dead_code)]
 	Mangle,
 }
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ProcessAssembly {
 	#[default]
 	Filter,
-	#[allow(dead_code)]
 	Raw,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
-#[allow(unused)]
 pub enum CompileTarget {
+	Asm,
+	#[serde(rename = "llvm-ir")]
+	LlvmIr,
 	Mir,
+	Hir,
+	Wasm,
+}
+
+impl CompileTarget {
+	/// Name of the command that compiles to this target
+	pub fn command(self) -> &'static str {
+		match self {
+			CompileTarget::Asm => "asm",
+			CompileTarget::LlvmIr => "ir",
+			CompileTarget::Mir => "mir",
+			CompileTarget::Hir => "hir",
+			CompileTarget::Wasm => "wasm",
+		}
+	}
 }
 
-#[allow(unused)]
 pub type CompileResponse = FormatResponse;
 
 #[derive(Debug, Clone, Copy, Serialize)]
@@ -132,6 +258,18 @@ impl FromStr for Channel {
 	}
 }
 
+impl Channel {
+	pub const ALL: [Channel; 3] = [Channel::Stable, Channel::Beta, Channel::Nightly];
+
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Channel::Stable => "stable",
+			Channel::Beta => "beta",
+			Channel::Nightly => "nightly",
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, Serialize)]
 pub enum Edition {
 	#[serde(rename = "2015")]
@@ -158,8 +296,25 @@ impl FromStr for Edition {
 	}
 }
 
+impl Edition {
+	pub const ALL: [Edition; 4] = [
+		Edition::E2015,
+		Edition::E2018,
+		Edition::E2021,
+		Edition::E2024,
+	];
+
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Edition::E2015 => "2015",
+			Edition::E2018 => "2018",
+			Edition::E2021 => "2021",
+			Edition::E2024 => "2024",
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, Serialize)]
-#[allow(unused)]
 pub enum CrateType {
 	#[serde(rename = "bin")]
 	Binary,
@@ -167,6 +322,156 @@ pub enum CrateType {
 	Library,
 }
 
+impl FromStr for CrateType {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"bin" => Ok(CrateType::Binary),
+			"lib" => Ok(CrateType::Library),
+			_ => bail!("invalid crate type `{}`", s),
+		}
+	}
+}
+
+impl CrateType {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			CrateType::Binary => "bin",
+			CrateType::Library => "lib",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+	X86_64,
+	Aarch64,
+}
+
+impl FromStr for Architecture {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"x86_64" => Ok(Architecture::X86_64),
+			"aarch64" => Ok(Architecture::Aarch64),
+			_ => bail!("invalid target `{}`", s),
+		}
+	}
+}
+
+impl Architecture {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Architecture::X86_64 => "x86_64",
+			Architecture::Aarch64 => "aarch64",
+		}
+	}
+
+	/// Target triple for cross-compilation
+	pub fn triple(self) -> &'static str {
+		match self {
+			Architecture::X86_64 => "x86_64-unknown-linux-gnu",
+			Architecture::Aarch64 => "aarch64-unknown-linux-gnu",
+		}
+	}
+}
+
+/// How Miri checks the validity of references
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasingModel {
+	Stacked,
+	Tree,
+}
+
+impl FromStr for AliasingModel {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"stacked" => Ok(AliasingModel::Stacked),
+			"tree" => Ok(AliasingModel::Tree),
+			_ => bail!("invalid aliasing model `{}`", s),
+		}
+	}
+}
+
+impl AliasingModel {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			AliasingModel::Stacked => "stacked",
+			AliasingModel::Tree => "tree",
+		}
+	}
+}
+
+/// The `main` function that code without one is wrapped in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+	/// A `#[tokio::main]` async main if the code uses `.await` outside of async functions and
+	/// blocks, a regular main otherwise
+	Auto,
+	/// A regular main
+	None,
+	/// A `#[tokio::main]` async main
+	Tokio,
+}
+
+impl FromStr for Runtime {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"auto" => Ok(Runtime::Auto),
+			"none" => Ok(Runtime::None),
+			"tokio" => Ok(Runtime::Tokio),
+			_ => bail!("invalid runtime `{}`", s),
+		}
+	}
+}
+
+impl Runtime {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Runtime::Auto => "auto",
+			Runtime::None => "none",
+			Runtime::Tokio => "tokio",
+		}
+	}
+}
+
+/// What ?playall runs the code on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Across {
+	/// Every channel, with the edition of the flags
+	Channels,
+	/// Every edition, with the channel of the flags
+	Editions,
+}
+
+impl FromStr for Across {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"channels" => Ok(Across::Channels),
+			"editions" => Ok(Across::Editions),
+			_ => bail!("invalid value `{}` for `across`", s),
+		}
+	}
+}
+
+impl Across {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Across::Channels => "channels",
+			Across::Editions => "editions",
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Mode {
@@ -186,7 +491,16 @@ impl FromStr for Mode {
 	}
 }
 
-#[derive(Debug)]
+impl Mode {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Mode::Debug => "debug",
+			Mode::Release => "release",
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
 pub struct PlayResult {
 	pub success: bool,
 	pub stdout: String,
@@ -230,47 +544,525 @@ impl<'de> Deserialize<'de> for PlayResult {
 	}
 }
 
-/// Returns a gist ID
-pub async fn post_gist(ctx: Context<'_>, code: &str) -> Result<String, Error> {
-	let mut payload = HashMap::new();
-	payload.insert("code", code);
+impl crate::cache::Cacheable for PlayResult {
+	/// Failed runs may have timed out or been stopped
+	fn is_cacheable(&self) -> bool {
+		self.success
+	}
+}
 
-	let resp = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/meta/gist/")
-		.header(header::REFERER, "https://discord.gg/rust-lang-community")
-		.json(&payload)
-		.send()
-		.await?;
+/// Messages sent by the playground over the websocket, see `ui/src/server_axum/websocket.rs` in
+/// the rust-playground repository
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum WsResponse {
+	#[serde(rename = "output/execute/wsExecuteStdout")]
+	ExecuteStdout { payload: String },
+	#[serde(rename = "output/execute/wsExecuteStderr")]
+	ExecuteStderr { payload: String },
+	#[serde(rename = "output/execute/wsExecuteEnd")]
+	ExecuteEnd { payload: WsExecuteEnd },
+	#[serde(rename = "websocket/error")]
+	Error { payload: WsError },
+	/// Feature flags, execution begin and status updates; we don't need those
+	#[serde(other)]
+	Other,
+}
 
-	let mut resp: HashMap<String, String> = resp.json().await?;
-	info!("gist response: {:?}", resp);
+#[derive(Debug, Deserialize)]
+struct WsExecuteEnd {
+	success: bool,
+}
 
-	let gist_id = resp.remove("id").ok_or(anyhow!("no gist found"))?;
-	Ok(gist_id)
+#[derive(Debug, Deserialize)]
+struct WsError {
+	error: String,
 }
 
-pub fn url_from_gist(flags: &CommandFlags, gist_id: &str) -> String {
-	format!(
-		"https://play.rust-lang.org/?version={}&mode={}&edition={}&gist={}",
-		match flags.channel {
-			Channel::Nightly => "nightly",
-			Channel::Beta => "beta",
-			Channel::Stable => "stable",
-		},
-		match flags.mode {
-			Mode::Debug => "debug",
-			Mode::Release => "release",
-		},
-		match flags.edition {
-			Edition::E2015 => "2015",
-			Edition::E2018 => "2018",
-			Edition::E2021 => "2021",
-			Edition::E2024 => "2024",
-		},
-		gist_id
-	)
+/// Boxed future returned by [`PlaygroundBackend`] methods, so the trait stays object safe
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send + 'a>>;
+
+pub const DEFAULT_PLAYGROUND_URL: &str = "https://play.rust-lang.org";
+
+/// Something that speaks the play.rust-lang.org API
+///
+/// All playground commands go through this instead of building HTTP requests themselves, so the
+/// bot can be pointed at a self-hosted playground or a local stand-in server.
+pub trait PlaygroundBackend: std::fmt::Debug + Send + Sync {
+	fn execute<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>>;
+
+	/// Like [`Self::execute`], but sends stdout and stderr chunks into `output` as they arrive, and
+	/// kills the program when `kill` is notified
+	///
+	/// Backends that can't stream only deliver the output as a whole at the end.
+	fn execute_streaming<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+		_output: mpsc::UnboundedSender<String>,
+		kill: Arc<Notify>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			tokio::select! {
+				result = self.execute(request) => result,
+				() = kill.notified() => Ok(PlayResult {
+					success: false,
+					stdout: String::new(),
+					stderr: "Execution was stopped\n".to_owned(),
+				}),
+			}
+		})
+	}
+
+	fn miri<'a>(&'a self, request: &'a MiriRequest<'a>)
+		-> BoxFuture<'a, Result<PlayResult, Error>>;
+
+	/// Whether [`Self::miri`] supports the Miri flags besides `aliasing`, like `seed`
+	fn supports_miri_flags(&self) -> bool {
+		false
+	}
+
+	fn macro_expansion<'a>(
+		&'a self,
+		request: &'a MacroExpansionRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>>;
+
+	fn clippy<'a>(
+		&'a self,
+		request: &'a ClippyRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>>;
+
+	fn format<'a>(
+		&'a self,
+		request: &'a FormatRequest<'a>,
+	) -> BoxFuture<'a, Result<FormatResponse, Error>>;
+
+	fn compile<'a>(
+		&'a self,
+		request: &'a CompileRequest<'a>,
+	) -> BoxFuture<'a, Result<CompileResponse, Error>>;
+
+	/// Uploads the code as a gist and returns the gist ID
+	fn gist<'a>(&'a self, code: &'a str) -> BoxFuture<'a, Result<String, Error>>;
+
+	/// Link that opens a gist created by [`Self::gist`] in the playground web UI
+	fn gist_url(&self, flags: &CommandFlags, gist_id: &str) -> String;
+}
+
+/// The regular playground, talking JSON over HTTP to `base_url`
+#[derive(Debug, Clone)]
+pub struct HttpPlayground {
+	http: reqwest::Client,
+	base_url: String,
+}
+
+impl HttpPlayground {
+	pub fn new(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+		let mut base_url = base_url.into();
+		// Allow both `https://play.example.org` and `https://play.example.org/` in the config
+		while base_url.ends_with('/') {
+			base_url.pop();
+		}
+		Self { http, base_url }
+	}
+
+	async fn post_json<T: DeserializeOwned>(
+		&self,
+		endpoint: &str,
+		body: &(impl Serialize + Sync),
+	) -> Result<T, Error> {
+		Ok(self
+			.http
+			.post(format!("{}/{endpoint}", self.base_url))
+			.json(body)
+			.send()
+			.await?
+			.json()
+			.await?)
+	}
+
+	async fn execute_over_websocket(
+		&self,
+		request: &PlaygroundRequest<'_>,
+		output: mpsc::UnboundedSender<String>,
+		kill: Arc<Notify>,
+	) -> Result<PlayResult, Error> {
+		// https:// becomes wss://, http:// becomes ws://
+		let url = format!("{}/websocket", self.base_url.replacen("http", "ws", 1));
+		let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+
+		let handshake = serde_json::json!({
+			"type": "websocket/connected",
+			"payload": { "iAcceptThisIsAnUnstableApi": true },
+			"meta": { "websocket": true, "sequenceNumber": 0 },
+		});
+		socket.send(WsMessage::Text(handshake.to_string())).await?;
+
+		let execute = serde_json::json!({
+			"type": "output/execute/wsExecuteRequest",
+			"payload": {
+				"channel": request.channel,
+				"mode": request.mode,
+				"edition": request.edition,
+				"crateType": request.crate_type,
+				"tests": request.tests,
+				"code": request.code,
+				"backtrace": request.backtrace,
+			},
+			"meta": { "sequenceNumber": 1 },
+		});
+		socket.send(WsMessage::Text(execute.to_string())).await?;
+
+		let mut stdout = String::new();
+		let mut stderr = String::new();
+		let mut killed = false;
+		loop {
+			let message = tokio::select! {
+				message = socket.next() => message,
+				() = kill.notified(), if !killed => {
+					killed = true;
+					let kill_request = serde_json::json!({
+						"type": "output/execute/wsExecuteKill",
+						"meta": { "sequenceNumber": 2 },
+					});
+					socket.send(WsMessage::Text(kill_request.to_string())).await?;
+					// The playground still sends the end of execution afterwards
+					continue;
+				}
+			};
+
+			let text = match message.ok_or(anyhow!("playground closed the websocket"))?? {
+				WsMessage::Text(text) => text,
+				WsMessage::Close(_) => bail!("playground closed the websocket"),
+				_ => continue,
+			};
+
+			match serde_json::from_str::<WsResponse>(&text)? {
+				WsResponse::ExecuteStdout { payload } => {
+					stdout += &payload;
+					// The receiver may have stopped listening, that's fine
+					let _: Result<_, _> = output.send(payload);
+				}
+				WsResponse::ExecuteStderr { payload } => {
+					stderr += &payload;
+					let _: Result<_, _> = output.send(payload);
+				}
+				WsResponse::ExecuteEnd { payload } => {
+					return Ok(PlayResult {
+						success: payload.success,
+						stdout,
+						stderr,
+					});
+				}
+				WsResponse::Error { payload } => bail!("playground error: {}", payload.error),
+				WsResponse::Other => {}
+			}
+		}
+	}
+}
+
+impl PlaygroundBackend for HttpPlayground {
+	fn execute<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.post_json("execute", request))
+	}
+
+	fn execute_streaming<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+		output: mpsc::UnboundedSender<String>,
+		kill: Arc<Notify>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.execute_over_websocket(request, output, kill))
+	}
+
+	fn miri<'a>(
+		&'a self,
+		request: &'a MiriRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			if request.strict_provenance || request.symbolic_alignment || request.seed != 0 {
+				bail!(
+					"The playground only supports the `aliasing` Miri flag, `{}` needs a local \
+					sandbox",
+					request.miri_flags()
+				);
+			}
+			self.post_json("miri", request).await
+		})
+	}
+
+	fn macro_expansion<'a>(
+		&'a self,
+		request: &'a MacroExpansionRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.post_json("macro-expansion", request))
+	}
+
+	fn clippy<'a>(
+		&'a self,
+		request: &'a ClippyRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.post_json("clippy", request))
+	}
+
+	fn format<'a>(
+		&'a self,
+		request: &'a FormatRequest<'a>,
+	) -> BoxFuture<'a, Result<FormatResponse, Error>> {
+		Box::pin(self.post_json("format", request))
+	}
+
+	fn compile<'a>(
+		&'a self,
+		request: &'a CompileRequest<'a>,
+	) -> BoxFuture<'a, Result<CompileResponse, Error>> {
+		Box::pin(async move {
+			if request.architecture != Architecture::X86_64 {
+				bail!(
+					"The playground only compiles for x86_64, `target={}` needs a local sandbox",
+					request.architecture.as_str()
+				);
+			}
+			self.post_json("compile", request).await
+		})
+	}
+
+	fn gist<'a>(&'a self, code: &'a str) -> BoxFuture<'a, Result<String, Error>> {
+		Box::pin(async move {
+			let mut payload = HashMap::new();
+			payload.insert("code", code);
+
+			let resp = self
+				.http
+				.post(format!("{}/meta/gist/", self.base_url))
+				.header(header::REFERER, "https://discord.gg/rust-lang-community")
+				.json(&payload)
+				.send()
+				.await?;
+
+			let mut resp: HashMap<String, String> = resp.json().await?;
+			info!("gist response: {:?}", resp);
+
+			let gist_id = resp.remove("id").ok_or(anyhow!("no gist found"))?;
+			Ok(gist_id)
+		})
+	}
+
+	fn gist_url(&self, flags: &CommandFlags, gist_id: &str) -> String {
+		format!(
+			"{}/?version={}&mode={}&edition={}&gist={}",
+			self.base_url,
+			flags.channel.as_str(),
+			flags.mode.as_str(),
+			flags.edition.as_str(),
+			gist_id
+		)
+	}
+}
+
+/// Sends every request to `primary` first, and retries on `fallback` if that fails
+#[derive(Debug)]
+pub struct FallbackPlayground {
+	pub primary: Arc<dyn PlaygroundBackend>,
+	pub fallback: Arc<dyn PlaygroundBackend>,
+}
+
+impl FallbackPlayground {
+	async fn call<'a, T>(
+		&'a self,
+		request: impl Fn(&'a dyn PlaygroundBackend) -> BoxFuture<'a, Result<T, Error>> + Send,
+	) -> Result<T, Error> {
+		match request(&*self.primary).await {
+			Ok(response) => Ok(response),
+			Err(e) => {
+				warn!(
+					"Playground backend {:?} failed, falling back: {}",
+					self.primary, e
+				);
+				request(&*self.fallback).await
+			}
+		}
+	}
+}
+
+impl PlaygroundBackend for FallbackPlayground {
+	fn execute<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.call(move |backend| backend.execute(request)))
+	}
+
+	fn execute_streaming<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+		output: mpsc::UnboundedSender<String>,
+		kill: Arc<Notify>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			// The output of the primary goes through a separate channel, to know whether it already
+			// streamed something. The fallback would show that output a second time, so in that case
+			// the error is returned instead
+			let (primary_output, mut primary_receiver) = mpsc::unbounded_channel();
+			let primary = self
+				.primary
+				.execute_streaming(request, primary_output, kill.clone());
+			tokio::pin!(primary);
+
+			let mut streamed = false;
+			let result = loop {
+				tokio::select! {
+					result = &mut primary => break result,
+					Some(chunk) = primary_receiver.recv() => {
+						streamed = true;
+						let _: Result<_, _> = output.send(chunk);
+					}
+				}
+			};
+			while let Ok(chunk) = primary_receiver.try_recv() {
+				streamed = true;
+				let _: Result<_, _> = output.send(chunk);
+			}
+
+			match result {
+				Err(e) if !streamed => {
+					warn!(
+						"Playground backend {:?} failed, falling back: {}",
+						self.primary, e
+					);
+					self.fallback.execute_streaming(request, output, kill).await
+				}
+				result => result,
+			}
+		})
+	}
+
+	fn miri<'a>(
+		&'a self,
+		request: &'a MiriRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.call(move |backend| backend.miri(request)))
+	}
+
+	fn supports_miri_flags(&self) -> bool {
+		self.primary.supports_miri_flags() || self.fallback.supports_miri_flags()
+	}
+
+	fn macro_expansion<'a>(
+		&'a self,
+		request: &'a MacroExpansionRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.call(move |backend| backend.macro_expansion(request)))
+	}
+
+	fn clippy<'a>(
+		&'a self,
+		request: &'a ClippyRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(self.call(move |backend| backend.clippy(request)))
+	}
+
+	fn format<'a>(
+		&'a self,
+		request: &'a FormatRequest<'a>,
+	) -> BoxFuture<'a, Result<FormatResponse, Error>> {
+		Box::pin(self.call(move |backend| backend.format(request)))
+	}
+
+	fn compile<'a>(
+		&'a self,
+		request: &'a CompileRequest<'a>,
+	) -> BoxFuture<'a, Result<CompileResponse, Error>> {
+		Box::pin(self.call(move |backend| backend.compile(request)))
+	}
+
+	fn gist<'a>(&'a self, code: &'a str) -> BoxFuture<'a, Result<String, Error>> {
+		// A gist ID is only meaningful to the backend that created it, so don't mix the two here
+		Box::pin(self.primary.gist(code))
+	}
+
+	fn gist_url(&self, flags: &CommandFlags, gist_id: &str) -> String {
+		self.primary.gist_url(flags, gist_id)
+	}
+}
+
+/// Which implementation of [`PlaygroundBackend`] runs the code
+#[derive(Debug, Clone, Copy)]
+pub enum BackendKind {
+	/// play.rust-lang.org, or whatever `PLAYGROUND_URL` points to
+	Remote,
+	/// [`LocalSandbox`] on the bot host
+	Local,
+}
+
+impl FromStr for BackendKind {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"remote" => Ok(BackendKind::Remote),
+			"local" => Ok(BackendKind::Local),
+			_ => bail!("invalid playground backend `{}`", s),
+		}
+	}
+}
+
+/// Builds the playground backend as configured by `PLAYGROUND_BACKEND` and
+/// `PLAYGROUND_FALLBACK` in the secret store
+pub fn backend_from_secrets(
+	secret_store: &SecretStore,
+	http: reqwest::Client,
+) -> Result<Arc<dyn PlaygroundBackend>, Error> {
+	let remote = HttpPlayground::new(
+		http,
+		secret_store
+			.get("PLAYGROUND_URL")
+			.unwrap_or_else(|| DEFAULT_PLAYGROUND_URL.to_owned()),
+	);
+	let kind = secret_store
+		.get("PLAYGROUND_BACKEND")
+		.map(|s| s.parse::<BackendKind>())
+		.transpose()?
+		.unwrap_or(BackendKind::Remote);
+	let fallback = secret_store
+		.get("PLAYGROUND_FALLBACK")
+		.map(|s| s.parse::<bool>())
+		.transpose()?
+		.unwrap_or(false);
+
+	if matches!(kind, BackendKind::Remote) && !fallback {
+		// Don't require any sandbox configuration if the sandbox isn't used
+		return Ok(Arc::new(remote));
+	}
+
+	let local = Arc::new(LocalSandbox::new(
+		SandboxConfig::from_secrets(secret_store)?,
+		remote.clone(),
+	));
+	let remote = Arc::new(remote);
+
+	let backend: Arc<dyn PlaygroundBackend> = match (kind, fallback) {
+		(BackendKind::Remote, _) => Arc::new(FallbackPlayground {
+			primary: remote,
+			fallback: local,
+		}),
+		(BackendKind::Local, false) => local,
+		(BackendKind::Local, true) => Arc::new(FallbackPlayground {
+			primary: local,
+			fallback: remote,
+		}),
+	};
+	Ok(backend)
+}
+
+/// Returns a gist ID
+pub async fn post_gist(ctx: Context<'_>, code: &str) -> Result<String, Error> {
+	ctx.data().playground.gist(code).await
 }
 
 pub async fn apply_online_rustfmt(
@@ -280,12 +1072,8 @@ pub async fn apply_online_rustfmt(
 ) -> Result<PlayResult, Error> {
 	let result = ctx
 		.data()
-		.http
-		.post("https://play.rust-lang.org/format")
-		.json(&FormatRequest { code, edition })
-		.send()
-		.await?
-		.json::<FormatResponse>()
+		.playground
+		.format(&FormatRequest { code, edition })
 		.await?;
 
 	Ok(PlayResult {
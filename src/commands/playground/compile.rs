@@ -0,0 +1,235 @@
+use anyhow::Error;
+
+use crate::helpers::code_block_or_attachment;
+use crate::types::Context;
+
+use super::{
+	api::{
+		Channel, CommandFlags, CompileRequest, CompileTarget, CrateType, DemangleAssembly,
+		PlayResult, ProcessAssembly,
+	},
+	util::{
+		format_play_eval_stderr, generic_help, parse_flags_with_code, run_on_playground,
+		send_reply, stub_message, GenericHelp,
+	},
+};
+
+// All the commands that show intermediate compiler output work the same, so this function
+// abstracts over them
+async fn compile_to(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+	target: CompileTarget,
+) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
+	ctx.say(stub_message(ctx)).await?;
+
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
+
+	let result = run_compile(ctx, &flags, &code, target).await?;
+
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { run_compile(ctx, &flags, code, target).await }),
+	)
+	.await
+}
+
+/// Compiles the code to the given target and returns the compiler output as stdout
+pub async fn run_compile(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+	target: CompileTarget,
+) -> Result<PlayResult, Error> {
+	let mut result = run_on_playground(ctx, target.command(), code, code, flags, async {
+		let response = ctx
+			.data()
+			.playground
+			.compile(&CompileRequest {
+				assembly_flavor: flags.asm_flavor,
+				backtrace: false,
+				// -Zunpretty only exists on nightly
+				channel: match target {
+					CompileTarget::Hir => Channel::Nightly,
+					_ => flags.channel,
+				},
+				code,
+				// Snippets without main are usually a bunch of functions to look at, which would all
+				// be optimized out as dead code in a binary
+				crate_type: flags.crate_type.unwrap_or(if code.contains("fn main") {
+					CrateType::Binary
+				} else {
+					CrateType::Library
+				}),
+				demangle_assembly: if flags.demangle {
+					DemangleAssembly::Demangle
+				} else {
+					DemangleAssembly::Mangle
+				},
+				edition: flags.edition,
+				mode: flags.mode,
+				process_assembly: if flags.filter {
+					ProcessAssembly::Filter
+				} else {
+					ProcessAssembly::Raw
+				},
+				target,
+				architecture: flags.target,
+				tests: false,
+			})
+			.await?;
+		Ok::<_, Error>(PlayResult {
+			success: response.success,
+			stdout: response.code,
+			stderr: response.stderr,
+		})
+	})
+	.await?;
+
+	result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
+	Ok(result)
+}
+
+/// Show the MIR of Rust code
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "mir_help",
+	category = "Playground"
+)]
+pub async fn mir(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	compile_to(ctx, flags, code, CompileTarget::Mir).await
+}
+
+#[must_use]
+pub fn mir_help() -> String {
+	generic_help(GenericHelp {
+		command: "mir",
+		desc: "Show the MIR (mid-level intermediate representation) that the code is lowered to",
+		flags: &["mode", "channel", "warn", "crate_type", "target"],
+		example_code: "code",
+	})
+}
+
+/// Show the HIR of Rust code
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "hir_help",
+	category = "Playground"
+)]
+pub async fn hir(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	compile_to(ctx, flags, code, CompileTarget::Hir).await
+}
+
+#[must_use]
+pub fn hir_help() -> String {
+	generic_help(GenericHelp {
+		command: "hir",
+		desc: "Show the HIR (high-level intermediate representation) of the code, which is \
+		roughly the code after desugaring. Always uses the nightly channel",
+		flags: &["mode", "channel", "warn", "crate_type"],
+		example_code: "code",
+	})
+}
+
+/// Show the LLVM IR of Rust code
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "ir_help",
+	category = "Playground"
+)]
+pub async fn ir(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	compile_to(ctx, flags, code, CompileTarget::LlvmIr).await
+}
+
+#[must_use]
+pub fn ir_help() -> String {
+	generic_help(GenericHelp {
+		command: "ir",
+		desc: "Show the LLVM IR that the code compiles to. Like ?llvmir, but on the playground \
+		instead of Godbolt",
+		flags: &["mode", "channel", "warn", "crate_type", "target"],
+		example_code: "code",
+	})
+}
+
+/// Show the assembly of Rust code
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "asm_help",
+	category = "Playground"
+)]
+pub async fn asm(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	compile_to(ctx, flags, code, CompileTarget::Asm).await
+}
+
+#[must_use]
+pub fn asm_help() -> String {
+	generic_help(GenericHelp {
+		command: "asm",
+		desc: "Show the assembly that the code compiles to, x86-64 unless `target=aarch64`. Like \
+		?godbolt, but on the playground instead of Godbolt",
+		flags: &[
+			"mode",
+			"channel",
+			"warn",
+			"flavor",
+			"demangle",
+			"filter",
+			"crate_type",
+			"target",
+		],
+		example_code: "code",
+	})
+}
+
+/// Show the WebAssembly of Rust code
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "wasm_help",
+	category = "Playground"
+)]
+pub async fn wasm(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	compile_to(ctx, flags, code, CompileTarget::Wasm).await
+}
+
+#[must_use]
+pub fn wasm_help() -> String {
+	generic_help(GenericHelp {
+		command: "wasm",
+		desc: "Show the WebAssembly that the code compiles to",
+		flags: &["mode", "channel", "warn", "crate_type"],
+		example_code: "code",
+	})
+}
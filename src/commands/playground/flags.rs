@@ -0,0 +1,434 @@
+//! The flags of the playground commands, like `mode=release` or `edition=2021`
+//!
+//! Parsing, the help texts, the cache key, the command history and the slash command choices are
+//! all generated from [`FLAGS`], so a new flag only needs to be added there and to
+//! [`CommandFlags`].
+
+use std::borrow::Cow;
+use std::fmt::Write as _;
+
+use anyhow::{anyhow, bail, Error};
+
+use crate::helpers::OutputMode;
+
+use super::api::{
+	Across, AliasingModel, Architecture, AssemblyFlavour, Channel, CommandFlags, CrateType,
+	Edition, Mode, Runtime,
+};
+
+/// The flags of a command invoked without any
+pub const DEFAULT_FLAGS: CommandFlags = CommandFlags {
+	channel: Channel::Nightly,
+	mode: Mode::Debug,
+	edition: Edition::E2024,
+	crate_type: None,
+	target: Architecture::X86_64,
+	warn: false,
+	run: false,
+	tests: false,
+	backtrace: false,
+	asm_flavor: AssemblyFlavour::Intel,
+	demangle: true,
+	filter: true,
+	both_channels: false,
+	chart: false,
+	expand: false,
+	runtime: Runtime::Auto,
+	repl: false,
+	across: Across::Channels,
+	aliasing: AliasingModel::Stacked,
+	strict_provenance: false,
+	symbolic_alignment: false,
+	seed: 0,
+	seeds: 1,
+	lints: Vec::new(),
+	output: OutputMode::Auto,
+	cache: true,
+};
+
+/// A type that flags can have
+trait FlagValue: Sized {
+	fn parse_flag(value: &str) -> Result<Self, Error>;
+	fn flag_str(&self) -> Cow<'static, str>;
+}
+
+impl FlagValue for bool {
+	fn parse_flag(value: &str) -> Result<Self, Error> {
+		match value {
+			"true" => Ok(true),
+			"false" => Ok(false),
+			_ => bail!("invalid boolean `{}`", value),
+		}
+	}
+
+	fn flag_str(&self) -> Cow<'static, str> {
+		Cow::Borrowed(if *self { "true" } else { "false" })
+	}
+}
+
+/// `auto` leaves the crate type up to the command
+impl FlagValue for Option<CrateType> {
+	fn parse_flag(value: &str) -> Result<Self, Error> {
+		match value {
+			"auto" => Ok(None),
+			_ => Ok(Some(value.parse()?)),
+		}
+	}
+
+	fn flag_str(&self) -> Cow<'static, str> {
+		Cow::Borrowed(self.map_or("auto", CrateType::as_str))
+	}
+}
+
+/// Comma-separated Clippy lints, with or without the `clippy::` prefix, or `none`
+impl FlagValue for Vec<String> {
+	fn parse_flag(value: &str) -> Result<Self, Error> {
+		if value == "none" {
+			return Ok(Vec::new());
+		}
+		value
+			.split(',')
+			.map(|lint| {
+				let lint = lint.trim();
+				let name = lint
+					.strip_prefix("clippy::")
+					.unwrap_or(lint)
+					.replace('-', "_");
+				if name.is_empty()
+					|| !name
+						.chars()
+						.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+				{
+					bail!("invalid lint `{}`", lint);
+				}
+				Ok(name)
+			})
+			.collect()
+	}
+
+	fn flag_str(&self) -> Cow<'static, str> {
+		if self.is_empty() {
+			Cow::Borrowed("none")
+		} else {
+			Cow::Owned(self.join(","))
+		}
+	}
+}
+
+macro_rules! impl_flag_value_for_number {
+	($($type:ty),*) => {$(
+		impl FlagValue for $type {
+			fn parse_flag(value: &str) -> Result<Self, Error> {
+				value
+					.parse()
+					.map_err(|_| anyhow!("invalid number `{}`", value))
+			}
+
+			fn flag_str(&self) -> Cow<'static, str> {
+				Cow::Owned(self.to_string())
+			}
+		}
+	)*};
+}
+
+impl_flag_value_for_number!(u32, u64);
+
+macro_rules! impl_flag_value {
+	($($type:ty),*) => {$(
+		impl FlagValue for $type {
+			fn parse_flag(value: &str) -> Result<Self, Error> {
+				value.parse()
+			}
+
+			fn flag_str(&self) -> Cow<'static, str> {
+				Cow::Borrowed(self.as_str())
+			}
+		}
+	)*};
+}
+
+impl_flag_value!(
+	Channel,
+	Mode,
+	Edition,
+	Architecture,
+	AssemblyFlavour,
+	Runtime,
+	Across,
+	AliasingModel,
+	OutputMode
+);
+
+pub struct Flag {
+	pub name: &'static str,
+	/// Possible values. Empty for numbers
+	pub values: &'static [&'static str],
+	/// Explanation for the help, if the values don't speak for themselves
+	pub help: &'static str,
+	/// Whether the flag can change the result of running code. Only those flags are part of the
+	/// cache key and of the command history
+	pub affects_result: bool,
+	set: fn(&mut CommandFlags, &str) -> Result<(), Error>,
+	get: fn(&CommandFlags) -> Cow<'static, str>,
+}
+
+impl Flag {
+	/// The value of this flag in `flags`
+	pub fn get(&self, flags: &CommandFlags) -> Cow<'static, str> {
+		(self.get)(flags)
+	}
+
+	pub fn default(&self) -> Cow<'static, str> {
+		self.get(&DEFAULT_FLAGS)
+	}
+
+	/// Possible values, for the help and for errors
+	pub fn values_str(&self) -> String {
+		if self.values.is_empty() {
+			"a number".to_owned()
+		} else {
+			self.values.join(", ")
+		}
+	}
+
+	/// Sets this flag in `flags`. The error suggests a value if `value` looks like a typo
+	pub fn set(&self, flags: &mut CommandFlags, value: &str) -> Result<(), Error> {
+		(self.set)(flags, value).map_err(|e| {
+			let mut message = e.to_string();
+			match did_you_mean(value, self.values.iter().copied()) {
+				Some(suggestion) => {
+					let _ = write!(message, ". Did you mean `{suggestion}`?");
+				}
+				None => message += ".",
+			}
+			anyhow!("{} Possible values: {}", message, self.values_str())
+		})
+	}
+}
+
+/// Values of the boolean flags, which are boolean options in slash commands
+pub const BOOL: &[&str] = &["true", "false"];
+
+macro_rules! flag {
+	($name:literal => $field:ident, $values:expr, $help:literal, affects_result: $affects_result:literal) => {
+		Flag {
+			name: $name,
+			values: $values,
+			help: $help,
+			affects_result: $affects_result,
+			set: |flags, value| {
+				flags.$field = FlagValue::parse_flag(value)?;
+				Ok(())
+			},
+			get: |flags| flags.$field.flag_str(),
+		}
+	};
+}
+
+/// All flags, in the order they're listed in the help
+pub const FLAGS: &[Flag] = &[
+	flag!("mode" => mode, &["debug", "release"], "", affects_result: true),
+	flag!("channel" => channel, &["stable", "beta", "nightly"], "", affects_result: true),
+	flag!("edition" => edition, &["2015", "2018", "2021", "2024"], "", affects_result: true),
+	flag!(
+		"crate_type" => crate_type,
+		&["auto", "bin", "lib"],
+		"`lib` compiles the code as a library instead of wrapping it in a `fn main`",
+		affects_result: true
+	),
+	flag!(
+		"target" => target,
+		&["x86_64", "aarch64"],
+		"`aarch64` needs a local sandbox, since play.rust-lang.org only compiles for x86_64",
+		affects_result: true
+	),
+	flag!("warn" => warn, BOOL, "", affects_result: true),
+	flag!("run" => run, BOOL, "", affects_result: true),
+	flag!(
+		"tests" => tests,
+		BOOL,
+		"runs the `#[test]` functions instead of `main`",
+		affects_result: true
+	),
+	flag!(
+		"backtrace" => backtrace,
+		BOOL,
+		"sets `RUST_BACKTRACE=1`",
+		affects_result: true
+	),
+	flag!("flavor" => asm_flavor, &["intel", "att"], "", affects_result: true),
+	flag!("demangle" => demangle, BOOL, "", affects_result: true),
+	flag!("filter" => filter, BOOL, "", affects_result: true),
+	flag!(
+		"both_channels" => both_channels,
+		BOOL,
+		"runs on stable and nightly",
+		affects_result: true
+	),
+	flag!(
+		"chart" => chart,
+		BOOL,
+		"renders the results as a bar chart",
+		affects_result: false
+	),
+	flag!(
+		"expand" => expand,
+		BOOL,
+		"shows the code after macro expansion",
+		affects_result: true
+	),
+	flag!(
+		"runtime" => runtime,
+		&["auto", "none", "tokio"],
+		"`auto` uses tokio if there's a top-level `.await`, the local sandbox has no tokio",
+		affects_result: true
+	),
+	flag!(
+		"repl" => repl,
+		BOOL,
+		"prints every top-level expression",
+		affects_result: true
+	),
+	flag!(
+		"across" => across,
+		&["channels", "editions"],
+		"what ?playall runs the code on",
+		affects_result: true
+	),
+	flag!(
+		"aliasing" => aliasing,
+		&["stacked", "tree"],
+		"checks references with Stacked Borrows or Tree Borrows",
+		affects_result: true
+	),
+	flag!(
+		"strict_provenance" => strict_provenance,
+		BOOL,
+		"forbids casting integers to pointers",
+		affects_result: true
+	),
+	flag!(
+		"symbolic_alignment" => symbolic_alignment,
+		BOOL,
+		"only trusts the alignment the types guarantee, not the actual addresses",
+		affects_result: true
+	),
+	flag!(
+		"seed" => seed,
+		&[],
+		"seeds the random number generator of Miri, which decides e.g. the thread scheduling",
+		affects_result: true
+	),
+	flag!(
+		"seeds" => seeds,
+		&[],
+		"runs Miri with this many consecutive seeds in parallel, to find bugs that only happen \
+		with some thread interleavings",
+		affects_result: true
+	),
+	flag!(
+		"lints" => lints,
+		&["none", "pedantic", "nursery", "restriction"],
+		"comma-separated lint groups or single lints that ?clippy enables, like \
+		`lints=pedantic,unwrap_used`",
+		affects_result: true
+	),
+	flag!("output" => output, &["auto", "pages", "file", "truncate"], "", affects_result: false),
+	flag!("cache" => cache, BOOL, "", affects_result: false),
+];
+
+/// The flags that every playground command takes
+pub const COMMON_FLAGS: [&str; 3] = ["edition", "output", "cache"];
+
+pub fn find_flag(name: &str) -> Option<&'static Flag> {
+	FLAGS.iter().find(|flag| flag.name == name)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<_>>();
+	let mut row = (0..=b.len()).collect::<Vec<_>>();
+	for (i, a) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, &b) in b.iter().enumerate() {
+			let substitution = diagonal + usize::from(a != b);
+			diagonal = row[j + 1];
+			row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+		}
+	}
+	row[b.len()]
+}
+
+/// The candidate closest to `input`, unless they're all too different to be a typo
+pub fn did_you_mean<'a>(input: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+	candidates
+		.map(|candidate| (edit_distance(input, candidate), candidate))
+		.filter(|&(distance, candidate)| distance <= 2.max(candidate.len() / 3))
+		.min_by_key(|&(distance, _)| distance)
+		.map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn set(name: &str, value: &str) -> Result<CommandFlags, String> {
+		let mut flags = DEFAULT_FLAGS;
+		find_flag(name)
+			.unwrap()
+			.set(&mut flags, value)
+			.map_err(|e| e.to_string())?;
+		Ok(flags)
+	}
+
+	#[test]
+	fn unknown_flags_get_a_suggestion() {
+		let names = || FLAGS.iter().map(|flag| flag.name);
+		assert_eq!(did_you_mean("chanel", names()), Some("channel"));
+		assert_eq!(did_you_mean("edtion", names()), Some("edition"));
+		assert_eq!(did_you_mean("xyz", names()), None);
+
+		let args = poise::KeyValueArgs([("mdoe".to_owned(), "release".to_owned())].into());
+		let (_, errors) = super::super::util::parse_flags(args);
+		assert_eq!(errors, "unknown flag `mdoe`. Did you mean `mode`?\n");
+	}
+
+	#[test]
+	fn invalid_values_are_rejected() {
+		assert_eq!(
+			set("mode", "relase").err().unwrap(),
+			"invalid compilation mode `relase`. Did you mean `release`? Possible values: debug, \
+			release"
+		);
+		assert_eq!(
+			set("channel", "weekly").err().unwrap(),
+			"invalid release channel `weekly`. Possible values: stable, beta, nightly"
+		);
+		assert_eq!(
+			set("seeds", "many").err().unwrap(),
+			"invalid number `many`. Possible values: a number"
+		);
+	}
+
+	#[test]
+	fn bools_are_parsed() {
+		assert!(set("warn", "true").unwrap().warn);
+		assert!(!set("demangle", "false").unwrap().demangle);
+		assert_eq!(
+			set("warn", "yes").err().unwrap(),
+			"invalid boolean `yes`. Possible values: true, false"
+		);
+		assert_eq!(
+			set("warn", "ture").err().unwrap(),
+			"invalid boolean `ture`. Did you mean `true`? Possible values: true, false"
+		);
+	}
+
+	#[test]
+	fn edit_distance_counts_single_char_edits() {
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+		assert_eq!(edit_distance("", "abc"), 3);
+		assert_eq!(edit_distance("flag", "flag"), 0);
+	}
+}
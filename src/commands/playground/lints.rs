@@ -0,0 +1,342 @@
+//! Parsing the human-readable output of Clippy into a compact list of lints, grouped by lint
+
+use std::fmt::Write as _;
+
+/// Where Clippy documents its lints. The lint name goes after the `#`
+const CLIPPY_LINTS_URL: &str = "https://rust-lang.github.io/rust-clippy/master/index.html#";
+
+/// Start of the documentation links in Clippy's output. Nightly Clippy links to `master`, stable
+/// Clippy to its version, like `rust-1.85.0`
+const CLIPPY_DOCS_URL: &str = "https://rust-lang.github.io/rust-clippy/";
+
+/// A warning or error that Clippy or rustc emitted
+struct Lint<'a> {
+	/// Like `clippy::needless_return` or `unused_variables`. Rustc only names its own lints on
+	/// their first occurrence, so this is `None` for the following ones
+	name: Option<String>,
+	level: &'a str,
+	message: &'a str,
+	line: u32,
+	/// The code at `line`
+	source: &'a str,
+}
+
+/// The name of a lint in an identifier-like string, e.g. `needless_return)]` in
+/// `#[warn(clippy::needless_return)]`
+fn lint_name(s: &str) -> &str {
+	let end = s
+		.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+		.unwrap_or(s.len());
+	&s[..end]
+}
+
+/// Parses one diagnostic, i.e. the header line and the lines up to the next diagnostic. Returns
+/// `None` for diagnostics without a location, like the `generated 2 warnings` summary
+fn parse_lint(diagnostic: &str) -> Option<Lint<'_>> {
+	let mut lines = diagnostic.lines();
+	let header = lines.next()?;
+	let (level, message) = header.split_once(": ")?;
+	let level = level.split('[').next()?;
+
+	let location = lines.find_map(|line| line.trim_start().strip_prefix("--> "))?;
+	let line = location.rsplit(':').nth(1)?.parse().ok()?;
+	let source = lines
+		.find_map(|snippet_line| {
+			let (number, source) = snippet_line.split_once('|')?;
+			(number.trim().parse::<u32>().ok() == Some(line)).then_some(source)
+		})
+		.unwrap_or("")
+		.trim();
+
+	// The documentation link is there on every occurrence of a Clippy lint, unlike the note about
+	// the lint level
+	let name = diagnostic
+		.split_once(CLIPPY_DOCS_URL)
+		.and_then(|(_, rest)| rest.split_once("index.html#"))
+		.map(|(_, rest)| format!("clippy::{}", lint_name(rest)))
+		.or_else(|| {
+			let (_, attribute) = diagnostic.split_once("= note: `#[")?;
+			let (_, rest) = attribute.split_once('(')?;
+			Some(lint_name(rest).to_owned())
+		});
+
+	Some(Lint {
+		name,
+		level,
+		message,
+		line,
+		source,
+	})
+}
+
+/// Splits the output into diagnostics, which start with a line like `warning: ...` or
+/// `error[E0308]: ...`
+fn parse_lints(stderr: &str) -> Vec<Lint<'_>> {
+	let is_header = |line: &str| line.starts_with("warning") || line.starts_with("error");
+
+	let mut starts = stderr
+		.match_indices('\n')
+		.map(|(i, _)| i + 1)
+		.filter(|&i| is_header(&stderr[i..]))
+		.collect::<Vec<_>>();
+	if is_header(stderr) {
+		starts.insert(0, 0);
+	}
+	starts.push(stderr.len());
+
+	starts
+		.windows(2)
+		.filter_map(|range| parse_lint(&stderr[range[0]..range[1]]))
+		.collect()
+}
+
+/// Renders the lints in Clippy's output compactly, grouped by lint and with a link to the
+/// documentation of Clippy lints. Returns `None` if the output has compile errors, because
+/// those need the full output to make sense
+pub fn render_lints(stderr: &str) -> Option<String> {
+	let lints = parse_lints(stderr);
+	if lints.is_empty()
+		|| lints
+			.iter()
+			.any(|lint| lint.level == "error" && lint.name.is_none())
+	{
+		return None;
+	}
+
+	let mut groups = Vec::<(Option<&str>, Vec<&Lint<'_>>)>::new();
+	for lint in &lints {
+		let name = lint.name.as_deref();
+		match groups.iter_mut().find(|(other, _)| *other == name) {
+			Some((_, group)) => group.push(lint),
+			None => groups.push((name, vec![lint])),
+		}
+	}
+
+	let mut output = String::new();
+	for (name, group) in groups {
+		let first = group[0];
+		match name {
+			Some(name) => {
+				let _ = writeln!(output, "{}[{}]: {}", first.level, name, first.message);
+				if let Some(name) = name.strip_prefix("clippy::") {
+					let _ = writeln!(output, "  {CLIPPY_LINTS_URL}{name}");
+				}
+			}
+			None => output += "other warnings:\n",
+		}
+		for lint in group {
+			let _ = write!(output, "  {:>3} | {}", lint.line, lint.source);
+			if name.is_none() || lint.message != first.message {
+				let _ = write!(output, "  // {}", lint.message);
+			}
+			output += "\n";
+		}
+	}
+	Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Nightly Clippy, with the same lint twice
+	const NIGHTLY: &str = r#"    Checking playground v0.0.1 (/playground)
+warning: unused variable: `unused`
+ --> src/main.rs:4:5
+  |
+4 | let unused = f(1) + g(2);
+  |     ^^^^^^ help: if this is intentional, prefix it with an underscore: `_unused`
+  |
+  = note: `#[warn(unused_variables)]` (part of `#[warn(unused)]`) on by default
+
+warning: unneeded `return` statement
+ --> src/main.rs:2:23
+  |
+2 | fn f(x: u32) -> u32 { return x; }
+  |                       ^^^^^^^^
+  |
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/master/index.html#needless_return
+  = note: `#[warn(clippy::needless_return)]` on by default
+help: remove `return`
+  |
+2 - fn f(x: u32) -> u32 { return x; }
+2 + fn f(x: u32) -> u32 { x}
+  |
+
+warning: unneeded `return` statement
+ --> src/main.rs:3:23
+  |
+3 | fn g(x: u32) -> u32 { return x + 1; }
+  |                       ^^^^^^^^^^^^
+  |
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/master/index.html#needless_return
+help: remove `return`
+  |
+3 - fn g(x: u32) -> u32 { return x + 1; }
+3 + fn g(x: u32) -> u32 { x + 1}
+  |
+
+warning: `playground` (bin "playground") generated 3 warnings
+    Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.52s"#;
+
+	/// Stable Clippy with `lints=pedantic`. The casts have a second span that points to the lint
+	/// level
+	const STABLE_PEDANTIC: &str = r#"    Checking playground v0.0.1 (/playground)
+warning: unused variable: `unused`
+ --> src/main.rs:5:5
+  |
+5 | let unused = 1;
+  |     ^^^^^^ help: if this is intentional, prefix it with an underscore: `_unused`
+  |
+  = note: `#[warn(unused_variables)]` (part of `#[warn(unused)]`) on by default
+
+warning: unneeded `return` statement
+ --> src/main.rs:3:5
+  |
+3 |     return x as u32;
+  |     ^^^^^^^^^^^^^^^
+  |
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/rust-1.95.0/index.html#needless_return
+  = note: `#[warn(clippy::needless_return)]` on by default
+help: remove `return`
+  |
+3 -     return x as u32;
+3 +     x as u32
+  |
+
+warning: casting `u64` to `u32` may truncate the value
+ --> src/main.rs:3:12
+  |
+3 |     return x as u32;
+  |            ^^^^^^^^
+  |
+  = help: if this is intentional allow the lint with `#[allow(clippy::cast_possible_truncation)]` ...
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/rust-1.95.0/index.html#cast_possible_truncation
+note: the lint level is defined here
+ --> src/main.rs:1:9
+  |
+1 | #![warn(clippy::pedantic)] #![allow(dead_code, clippy::let_unit_value)] fn main() { let _ = {
+  |         ^^^^^^^^^^^^^^^^
+  = note: `#[warn(clippy::cast_possible_truncation)]` implied by `#[warn(clippy::pedantic)]`
+help: ... or use `try_from` and handle the error accordingly
+  |
+3 -     return x as u32;
+3 +     return u32::try_from(x);
+  |
+
+warning: casting `usize` to `u32` may truncate the value on targets with 64-bit wide pointers
+ --> src/main.rs:7:23
+  |
+7 | println!("{}", f(2) + v.len() as u32);
+  |                       ^^^^^^^^^^^^^^
+  |
+  = help: if this is intentional allow the lint with `#[allow(clippy::cast_possible_truncation)]` ...
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/rust-1.95.0/index.html#cast_possible_truncation
+help: ... or use `try_from` and handle the error accordingly
+  |
+7 - println!("{}", f(2) + v.len() as u32);
+7 + println!("{}", f(2) + u32::try_from(v.len()));
+  |
+
+warning: matching over `()` is more explicit
+ --> src/main.rs:1:89
+  |
+1 | #![warn(clippy::pedantic)] #![allow(dead_code, clippy::let_unit_value)] fn main() { let _ = {
+  |                                                                                         ^ help: use `()` instead of `_`: `()`
+  |
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/rust-1.95.0/index.html#ignored_unit_patterns
+  = note: `#[warn(clippy::ignored_unit_patterns)]` implied by `#[warn(clippy::pedantic)]`
+
+warning: useless use of `vec!`
+ --> src/main.rs:6:9
+  |
+6 | let v = vec![1, 2];
+  |         ^^^^^^^^^^ help: you can use an array directly: `[1, 2]`
+  |
+  = help: for further information visit https://rust-lang.github.io/rust-clippy/rust-1.95.0/index.html#useless_vec
+  = note: `#[warn(clippy::useless_vec)]` on by default
+
+warning: `playground` (bin "playground") generated 6 warnings
+    Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.52s"#;
+
+	const COMPILE_ERROR: &str = r#"    Checking playground v0.0.1 (/playground)
+error[E0308]: mismatched types
+ --> src/main.rs:2:14
+  |
+2 | let x: u32 = "a";
+  |        ---   ^^^ expected `u32`, found `&str`
+  |        |
+  |        expected due to this
+
+For more information about this error, try `rustc --explain E0308`.
+error: could not compile `playground` (bin "playground") due to 1 previous error"#;
+
+	#[test]
+	fn lints_are_parsed() {
+		let lints = parse_lints(NIGHTLY);
+		let parsed = lints
+			.iter()
+			.map(|lint| (lint.name.as_deref(), lint.level, lint.line, lint.source))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			parsed,
+			[
+				(
+					Some("unused_variables"),
+					"warning",
+					4,
+					"let unused = f(1) + g(2);"
+				),
+				(
+					Some("clippy::needless_return"),
+					"warning",
+					2,
+					"fn f(x: u32) -> u32 { return x; }"
+				),
+				(
+					Some("clippy::needless_return"),
+					"warning",
+					3,
+					"fn g(x: u32) -> u32 { return x + 1; }"
+				),
+			]
+		);
+	}
+
+	#[test]
+	fn lints_are_grouped() {
+		assert_eq!(
+			render_lints(NIGHTLY).unwrap(),
+			"\
+warning[unused_variables]: unused variable: `unused`
+    4 | let unused = f(1) + g(2);
+warning[clippy::needless_return]: unneeded `return` statement
+  https://rust-lang.github.io/rust-clippy/master/index.html#needless_return
+    2 | fn f(x: u32) -> u32 { return x; }
+    3 | fn g(x: u32) -> u32 { return x + 1; }
+"
+		);
+	}
+
+	#[test]
+	fn only_the_primary_span_counts() {
+		let rendered = render_lints(STABLE_PEDANTIC).unwrap();
+		// Stable Clippy links to its own version of the documentation
+		assert!(rendered.contains(concat!(
+			"warning[clippy::cast_possible_truncation]: casting `u64` to `u32` may truncate the ",
+			"value\n",
+			"  https://rust-lang.github.io/rust-clippy/master/index.html#cast_possible_truncation\n",
+			"    3 | return x as u32;\n",
+			"    7 | println!(\"{}\", f(2) + v.len() as u32);  // casting `usize` to `u32` may ",
+			"truncate the value on targets with 64-bit wide pointers\n",
+		)));
+		assert!(rendered.contains("\n    6 | let v = vec![1, 2];\n"));
+		assert_eq!(rendered.matches("warning[").count(), 5);
+	}
+
+	#[test]
+	fn compile_errors_need_the_full_output() {
+		assert!(render_lints(COMPILE_ERROR).is_none());
+		assert!(render_lints("    Finished `dev` profile [unoptimized + debuginfo]\n").is_none());
+	}
+}
@@ -1,23 +1,28 @@
-use anyhow::Error;
+use std::fmt::Write as _;
+use std::sync::LazyLock;
 
+use anyhow::{bail, Error};
+use tracing::warn;
+
+use crate::helpers::code_block_or_attachment;
 use crate::types::Context;
 
 use super::{
-	api::{CrateType, Mode, PlayResult, PlaygroundRequest},
+	api::{Channel, CommandFlags, CrateType, Mode, PlayResult, PlaygroundRequest},
 	util::{
-		format_play_eval_stderr, generic_help, hoise_crate_attributes, parse_flags, send_reply,
-		stub_message, GenericHelp,
+		format_play_eval_stderr, generic_help, hoise_crate_attributes, parse_flags_with_code,
+		run_on_playground, send_reply, stub_message, GenericHelp,
 	},
 };
 
 const BENCH_FUNCTION: &str = r#"
-fn bench(functions: &[(&str, fn())]) {
+fn bench(functions: &[(&str, &dyn Fn())]) {
     const CHUNK_SIZE: usize = 1000;
 
     // Warm up
-    for (_, function) in functions.iter() {
+    for (_, function) in functions {
         for _ in 0..CHUNK_SIZE {
-            (function)();
+            function();
         }
     }
 
@@ -28,35 +33,110 @@ fn bench(functions: &[(&str, fn())]) {
         for (chunk_times, (_, function)) in functions_chunk_times.iter_mut().zip(functions) {
             let start = std::time::Instant::now();
             for _ in 0..CHUNK_SIZE {
-                (function)();
+                function();
             }
             chunk_times.push((std::time::Instant::now() - start).as_secs_f64() / CHUNK_SIZE as f64);
         }
     }
 
-    for (chunk_times, (function_name, _)) in functions_chunk_times.iter().zip(functions) {
-        let mean_time: f64 = chunk_times.iter().sum::<f64>() / chunk_times.len() as f64;
-        
-        let mut sum_of_squared_deviations = 0.0;
-        let mut n = 0;
-        for &time in chunk_times {
-            // Filter out outliers (there are some crazy outliers, I've checked)
-            if time < mean_time * 3.0 {
-                sum_of_squared_deviations += (time - mean_time).powi(2);
-                n += 1;
-            }
-        }
-        let standard_deviation = f64::sqrt(sum_of_squared_deviations / n as f64);
+    // Percentiles instead of mean and standard deviation, because there are some crazy outliers
+    fn percentile(sorted_times: &[f64], p: f64) -> f64 {
+        sorted_times[((sorted_times.len() - 1) as f64 * p).round() as usize]
+    }
+    for chunk_times in &mut functions_chunk_times {
+        chunk_times.sort_by(f64::total_cmp);
+    }
+    let fastest_median = functions_chunk_times
+        .iter()
+        .map(|chunk_times| percentile(chunk_times, 0.5))
+        .fold(f64::INFINITY, f64::min);
 
+    for (chunk_times, (function_name, _)) in functions_chunk_times.iter().zip(functions) {
+        let median = percentile(chunk_times, 0.5);
+        let relative = if median <= fastest_median {
+            "fastest".to_string()
+        } else {
+            format!("{:.2}x slower", median / fastest_median)
+        };
         println!(
-            "{}: {:.1}ns ± {:.1}",
+            "{}: {:.1}ns (p5 {:.1}ns, p95 {:.1}ns), {}",
             function_name,
-            mean_time * 1_000_000_000.0,
-            standard_deviation * 1_000_000_000.0,
+            median * 1_000_000_000.0,
+            percentile(chunk_times, 0.05) * 1_000_000_000.0,
+            percentile(chunk_times, 0.95) * 1_000_000_000.0,
+            relative,
         );
     }
 }"#;
 
+/// A function to benchmark
+struct BenchFunction {
+	name: String,
+	/// Whether the function takes the output of the `setup` function
+	takes_setup: bool,
+}
+
+/// The functions that [`find_bench_functions`] found
+#[derive(Default)]
+struct BenchFunctions {
+	functions: Vec<BenchFunction>,
+	has_setup: bool,
+	/// Public functions that can't be benchmarked, with the reason
+	skipped: Vec<String>,
+}
+
+/// Finds the public functions to benchmark, and the `setup` function if there is one
+fn find_bench_functions(code: &str) -> Result<BenchFunctions, syn::Error> {
+	let file = syn::parse_file(code)?;
+	let functions = file.items.iter().filter_map(|item| match item {
+		syn::Item::Fn(function) => Some(function),
+		_ => None,
+	});
+
+	let mut found = BenchFunctions::default();
+	if let Some(setup) = functions.clone().find(|f| f.sig.ident == "setup") {
+		if !setup.sig.inputs.is_empty() || !setup.sig.generics.params.is_empty() {
+			return Err(syn::Error::new_spanned(
+				&setup.sig,
+				"the `setup` function must not take parameters or be generic",
+			));
+		}
+		found.has_setup = true;
+	}
+
+	for function in functions {
+		let name = function.sig.ident.to_string();
+		if name == "setup" || !matches!(function.vis, syn::Visibility::Public(_)) {
+			continue;
+		}
+
+		let skip_reason = if !function.sig.generics.params.is_empty() {
+			Some("it's generic")
+		} else if function.sig.asyncness.is_some() {
+			Some("it's async")
+		} else if function.sig.unsafety.is_some() {
+			Some("it's unsafe")
+		} else if function.sig.inputs.len() > 1 {
+			Some("it takes more than one parameter")
+		} else if function.sig.inputs.len() == 1 && !found.has_setup {
+			Some("it takes a parameter, but there's no `setup` function")
+		} else {
+			None
+		};
+		match skip_reason {
+			Some(reason) => found
+				.skipped
+				.push(format!("Skipped `{name}` because {reason}\n")),
+			None => found.functions.push(BenchFunction {
+				takes_setup: function.sig.inputs.len() == 1,
+				name,
+			}),
+		}
+	}
+
+	Ok(found)
+}
+
 /// Benchmark small snippets of code
 #[poise::command(
 	prefix_command,
@@ -67,74 +147,312 @@ fn bench(functions: &[(&str, fn())]) {
 pub async fn microbench(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
+	let user_code = code_block_or_attachment(ctx, code, 0).await?;
 	ctx.say(stub_message(ctx)).await?;
+	let (flags, mut flag_parse_errors) = parse_flags_with_code(flags, &user_code);
+
+	let Some((code, result)) = run_microbench(ctx, &flags, &user_code).await? else {
+		return Ok(());
+	};
 
-	let user_code = &code.code;
-	let black_box_hint = !user_code.contains("black_box");
+	if !user_code.contains("black_box") {
+		flag_parse_errors += BLACK_BOX_HINT;
+	}
+	let user_code: &str = &user_code;
+	send_reply(
+		ctx,
+		result,
+		&code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { rerun_microbench(ctx, &flags, user_code).await }),
+	)
+	.await
+}
 
+/// [`run_microbench`] for the buttons below the reply. The code was benchmarked before, so there
+/// is always something to benchmark
+pub async fn rerun_microbench(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	user_code: &str,
+) -> Result<PlayResult, Error> {
+	match run_microbench(ctx, flags, user_code).await? {
+		Some((_, result)) => Ok(result),
+		None => bail!("There's nothing to benchmark"),
+	}
+}
+
+pub const BLACK_BOX_HINT: &str =
+	"Hint: use the black_box function to prevent computations from being optimized out\n";
+
+/// Benchmarks the public functions in the code. Returns the code as it was sent to the
+/// playground, along with the result. If there's nothing to benchmark, the user is told so and
+/// `None` is returned
+pub async fn run_microbench(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	user_code: &str,
+) -> Result<Option<(String, PlayResult)>, Error> {
 	// insert convenience import for users
 	let after_crate_attrs = "#[allow(unused_imports)] use std::hint::black_box;\n";
 
-	let pub_fn_indices = user_code.match_indices("pub fn ").collect::<Vec<_>>();
-	match pub_fn_indices.len() {
+	let bench_functions = find_bench_functions(user_code);
+	// See `maybe_wrapped`
+	proc_macro2::extra::invalidate_current_thread_spans();
+	let bench_functions = match bench_functions {
+		Ok(bench_functions) => bench_functions,
+		Err(e) => {
+			ctx.say(format!("Failed to parse the code: {e}")).await?;
+			return Ok(None);
+		}
+	};
+	match bench_functions.functions.len() {
 		0 => {
 			ctx.say("No public functions (`pub fn`) found for benchmarking :thinking:")
 				.await?;
-			return Ok(());
+			return Ok(None);
 		}
 		1 => {
 			ctx.say("Please include multiple functions. Times are not comparable across runs")
 				.await?;
-			return Ok(());
+			return Ok(None);
 		}
 		_ => {}
 	};
 
 	// insert this after user code
 	let mut after_code = BENCH_FUNCTION.to_owned();
-	after_code += "fn main() {\nbench(&[";
-	for (index, _) in pub_fn_indices {
-		let function_name_start = index + "pub fn ".len();
-		let function_name_end = match user_code[function_name_start..].find('(') {
-			Some(x) => x + function_name_start,
-			None => continue,
-		};
-		let function_name = user_code[function_name_start..function_name_end].trim();
-
-		after_code += &format!("(\"{function_name}\", {function_name}), ");
+	after_code += "fn main() {\n";
+	if bench_functions.has_setup {
+		after_code += "let data = setup();\n";
+	}
+	after_code += "bench(&[";
+	for function in &bench_functions.functions {
+		let argument = if function.takes_setup { "&data" } else { "" };
+		let _ = write!(
+			after_code,
+			"(\"{0}\", &|| {{ std::hint::black_box({0}({1})); }}), ",
+			function.name, argument
+		);
 	}
 	after_code += "]);\n}\n";
 
 	// final assembled code
 	let code = hoise_crate_attributes(user_code, after_crate_attrs, &after_code);
 
-	let (flags, mut flag_parse_errors) = parse_flags(flags);
-	let mut result: PlayResult = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/execute")
-		.json(&PlaygroundRequest {
-			code: &code,
-			channel: flags.channel,
-			crate_type: CrateType::Binary,
-			edition: flags.edition,
-			mode: Mode::Release, // benchmarks on debug don't make sense
-			tests: false,
+	let request = |channel| PlaygroundRequest {
+		code: &code,
+		channel,
+		crate_type: CrateType::Binary,
+		edition: flags.edition,
+		mode: Mode::Release, // benchmarks on debug don't make sense
+		tests: false,
+		backtrace: false,
+	};
+	let mut result = run_on_playground(ctx, "microbench", user_code, &code, flags, async {
+		let playground = &ctx.data().playground;
+		if !flags.both_channels {
+			return playground.execute(&request(flags.channel)).await;
+		}
+
+		let (stable_request, nightly_request) =
+			(request(Channel::Stable), request(Channel::Nightly));
+		let (stable, nightly) = futures_util::future::join(
+			playground.execute(&stable_request),
+			playground.execute(&nightly_request),
+		)
+		.await;
+		let (stable, nightly) = (stable?, nightly?);
+		Ok(PlayResult {
+			success: stable.success && nightly.success,
+			stdout: format!("Stable:\n{}\nNightly:\n{}", stable.stdout, nightly.stdout),
+			// Both channels usually print the same warnings, so only show the errors of one
+			stderr: if stable.success {
+				nightly.stderr
+			} else {
+				stable.stderr
+			},
 		})
-		.send()
-		.await?
-		.json()
-		.await?;
+	})
+	.await?;
 
 	result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
+	for note in &bench_functions.skipped {
+		result.stdout += note;
+	}
 
-	if black_box_hint {
-		flag_parse_errors +=
-			"Hint: use the black_box function to prevent computations from being optimized out\n";
+	Ok(Some((code, result)))
+}
+
+/// One line of the output of `BENCH_FUNCTION`, times in nanoseconds
+struct Measurement {
+	label: String,
+	median: f64,
+	p5: f64,
+	p95: f64,
+}
+
+/// Parses a line like `name: 1.2ns (p5 1.1ns, p95 1.5ns), fastest`
+fn parse_measurement(line: &str) -> Option<Measurement> {
+	let (name, rest) = line.split_once(": ")?;
+	let (median, rest) = rest.split_once("ns (p5 ")?;
+	let (p5, rest) = rest.split_once("ns, p95 ")?;
+	let (p95, _) = rest.split_once("ns)")?;
+	Some(Measurement {
+		label: name.to_owned(),
+		median: median.parse().ok()?,
+		p5: p5.parse().ok()?,
+		p95: p95.parse().ok()?,
+	})
+}
+
+/// Parses the lines printed by `BENCH_FUNCTION`. With `both_channels=true`, the channel is added
+/// to the labels
+fn parse_bench_output(stdout: &str) -> Vec<Measurement> {
+	let mut channel = None;
+	let mut measurements = Vec::new();
+	for line in stdout.lines() {
+		match line {
+			"Stable:" => channel = Some("stable"),
+			"Nightly:" => channel = Some("nightly"),
+			_ => {}
+		}
+
+		if let Some(mut measurement) = parse_measurement(line) {
+			if let Some(channel) = channel {
+				measurement.label = format!("{} ({channel})", measurement.label);
+			}
+			measurements.push(measurement);
+		}
+	}
+	measurements
+}
+
+/// Renders the benchmark results in `stdout` as a PNG bar chart of the medians, with error bars
+/// from the 5th to the 95th percentile. Returns `None` if there are no results
+// Pixel coordinates are small, so the casts between them and the measurements don't lose anything
+#[allow(
+	clippy::cast_possible_truncation,
+	clippy::cast_precision_loss,
+	clippy::cast_sign_loss
+)]
+pub fn render_chart(stdout: &str) -> Option<Vec<u8>> {
+	static FONT: LazyLock<rusttype::Font<'_>> = LazyLock::new(|| {
+		rusttype::Font::try_from_bytes(include_bytes!("../../../assets/OpenSans.ttf"))
+			.expect("failed to load font")
+	});
+	const WIDTH: u32 = 800;
+	const ROW_HEIGHT: u32 = 40;
+	const MARGIN: u32 = 10;
+	const MAX_LABEL_CHARS: usize = 30;
+	// Space for the median next to the error bar
+	const VALUE_WIDTH: u32 = 110;
+	const BACKGROUND: image::Rgba<u8> = image::Rgba([49, 51, 56, 255]);
+	const TEXT: image::Rgba<u8> = image::Rgba([219, 222, 225, 255]);
+	const BAR: image::Rgba<u8> = image::Rgba([222, 165, 132, 255]);
+	const ERROR_BAR: image::Rgba<u8> = image::Rgba([255, 255, 255, 255]);
+
+	let measurements = parse_bench_output(stdout);
+	if measurements.is_empty() {
+		return None;
+	}
+	let labels = measurements
+		.iter()
+		.map(|m| {
+			if m.label.chars().count() > MAX_LABEL_CHARS {
+				m.label
+					.chars()
+					.take(MAX_LABEL_CHARS - 1)
+					.collect::<String>()
+					+ "…"
+			} else {
+				m.label.clone()
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let scale = rusttype::Scale::uniform(20.0);
+	let label_width = labels
+		.iter()
+		.map(|label| imageproc::drawing::text_size(scale, &FONT, label).0)
+		.max()
+		.unwrap_or(0) as u32
+		+ 2 * MARGIN;
+	let plot_width = WIDTH
+		.saturating_sub(label_width + VALUE_WIDTH + MARGIN)
+		.max(100);
+	let max_time = measurements.iter().map(|m| m.p95).fold(0.0, f64::max);
+	let x = |time: f64| {
+		label_width as f32 + (time / max_time.max(f64::MIN_POSITIVE) * f64::from(plot_width)) as f32
+	};
+
+	let height = measurements.len() as u32 * ROW_HEIGHT + 2 * MARGIN;
+	let mut image = image::RgbaImage::from_pixel(
+		label_width + plot_width + VALUE_WIDTH + MARGIN,
+		height,
+		BACKGROUND,
+	);
+	for (i, (measurement, label)) in measurements.iter().zip(&labels).enumerate() {
+		let top = MARGIN + i as u32 * ROW_HEIGHT;
+		let center = (top + ROW_HEIGHT / 2) as f32;
+		let text_top = top as i32 + 8;
+
+		imageproc::drawing::draw_text_mut(
+			&mut image,
+			TEXT,
+			MARGIN as i32,
+			text_top,
+			scale,
+			&FONT,
+			label,
+		);
+		let bar_width = (x(measurement.median) - label_width as f32).max(1.0) as u32;
+		imageproc::drawing::draw_filled_rect_mut(
+			&mut image,
+			imageproc::rect::Rect::at(label_width as i32, top as i32 + 6)
+				.of_size(bar_width, ROW_HEIGHT - 12),
+			BAR,
+		);
+
+		let (p5, p95) = (x(measurement.p5), x(measurement.p95));
+		imageproc::drawing::draw_line_segment_mut(
+			&mut image,
+			(p5, center),
+			(p95, center),
+			ERROR_BAR,
+		);
+		for end in [p5, p95] {
+			imageproc::drawing::draw_line_segment_mut(
+				&mut image,
+				(end, center - 6.0),
+				(end, center + 6.0),
+				ERROR_BAR,
+			);
+		}
+
+		imageproc::drawing::draw_text_mut(
+			&mut image,
+			TEXT,
+			p95 as i32 + 8,
+			text_top,
+			scale,
+			&FONT,
+			&format!("{:.1}ns", measurement.median),
+		);
 	}
-	send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+
+	let mut png = Vec::new();
+	image::DynamicImage::ImageRgba8(image)
+		.write_to(
+			&mut std::io::Cursor::new(&mut png),
+			image::ImageOutputFormat::Png,
+		)
+		.map_err(|e| warn!("failed to encode benchmark chart: {}", e))
+		.ok()?;
+	Some(png)
 }
 
 #[must_use]
@@ -144,15 +462,18 @@ pub fn microbench_help() -> String {
 		desc: "\
 Benchmarks small snippets of code by running them repeatedly. Public functions \
 are run in blocks of 1000 repetitions in a cycle until 5 seconds have \
-passed. Measurements are averaged and standard deviation is calculated for each
+passed. For each function, the median time, the 5th and 95th percentile and the speed \
+relative to the fastest function are shown
+
+If there's a `fn setup() -> T`, it's run once and the benchmarked functions can take its output \
+as a parameter of type `&T`. Pass `both_channels=true` to benchmark on stable and nightly, and \
+`chart=true` to get the results as a bar chart
 
 Use the `std::hint::black_box` function, which is already imported, to wrap results of \
 computations that shouldn't be optimized out. Also wrap computation inputs in `black_box(...)` \
 that should be opaque to the optimizer: `number * 2` produces optimized integer doubling assembly while \
 `number * black_box(2)` produces a generic integer multiplication instruction",
-		mode_and_channel: false,
-		warn: true,
-		run: false,
+		flags: &["warn", "channel", "both_channels", "chart"],
 		example_code: "
 pub fn add() {
     black_box(black_box(42.0) + black_box(99.0));
@@ -163,3 +484,83 @@ pub fn mul() {
 ",
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn names(found: &BenchFunctions) -> Vec<&str> {
+		found
+			.functions
+			.iter()
+			.map(|function| function.name.as_str())
+			.collect()
+	}
+
+	#[test]
+	fn public_functions_are_benchmarked() {
+		let found =
+			find_bench_functions("pub fn a() {}\nfn helper() {}\npub fn b() -> u32 { 1 }").unwrap();
+		assert_eq!(names(&found), ["a", "b"]);
+		assert!(!found.has_setup);
+		assert!(found.skipped.is_empty());
+	}
+
+	#[test]
+	fn setup_output_is_passed_on() {
+		let found = find_bench_functions(
+			"fn setup() -> Vec<u32> { vec![1] }\npub fn sum(v: Vec<u32>) -> u32 { v.iter().sum() }",
+		)
+		.unwrap();
+		assert!(found.has_setup);
+		assert_eq!(names(&found), ["sum"]);
+		assert!(found.functions[0].takes_setup);
+
+		assert!(find_bench_functions("fn setup(x: u32) {}").is_err());
+	}
+
+	#[test]
+	fn other_items_are_skipped() {
+		let found = find_bench_functions(
+			"pub struct S;\nimpl S { pub fn method() {} }\npub const N: u32 = 1;\n\
+			pub fn generic<T>() {}\npub async fn asynchronous() {}\npub fn takes(x: u32) {}\n\
+			pub fn bench() {}",
+		)
+		.unwrap();
+		assert_eq!(names(&found), ["bench"]);
+		assert_eq!(
+			found.skipped,
+			[
+				"Skipped `generic` because it's generic\n",
+				"Skipped `asynchronous` because it's async\n",
+				"Skipped `takes` because it takes a parameter, but there's no `setup` function\n",
+			]
+		);
+	}
+
+	#[test]
+	fn measurements_are_parsed() {
+		let measurement =
+			parse_measurement("sum: 12.5ns (p5 11.0ns, p95 20.3ns), 1.5x slower").unwrap();
+		assert_eq!(measurement.label, "sum");
+		assert!((measurement.median - 12.5).abs() < f64::EPSILON);
+		assert!((measurement.p5 - 11.0).abs() < f64::EPSILON);
+		assert!((measurement.p95 - 20.3).abs() < f64::EPSILON);
+
+		assert!(parse_measurement("Stable:").is_none());
+		assert!(parse_measurement("sum: fast").is_none());
+	}
+
+	#[test]
+	fn channels_are_added_to_the_labels() {
+		let measurements = parse_bench_output(
+			"Stable:\nf: 1.0ns (p5 1.0ns, p95 1.0ns), fastest\n\
+			Nightly:\nf: 2.0ns (p5 2.0ns, p95 2.0ns), 2.0x slower\n",
+		);
+		let labels = measurements
+			.iter()
+			.map(|measurement| measurement.label.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(labels, ["f (stable)", "f (nightly)"]);
+	}
+}
@@ -1,21 +1,150 @@
 use std::borrow::Cow;
 
-use anyhow::Error;
+use anyhow::{anyhow, bail, Error};
 use tracing::warn;
 
+use crate::helpers::code_block_or_attachment;
 use crate::types::Context;
 
 use super::{
 	api::{
-		apply_online_rustfmt, ClippyRequest, CrateType, MacroExpansionRequest, MiriRequest,
-		PlayResult,
+		apply_online_rustfmt, ClippyRequest, CommandFlags, CrateType, MacroExpansionRequest,
+		MiriRequest, PlayResult,
 	},
+	lints::render_lints,
 	util::{
-		extract_relevant_lines, generic_help, maybe_wrap, maybe_wrapped, parse_flags, send_reply,
-		strip_fn_main_boilerplate_from_formatted, stub_message, GenericHelp, ResultHandling,
+		extract_relevant_lines, generic_help, maybe_wrap, maybe_wrapped, parse_flags_with_code,
+		run_on_playground, send_reply, strip_fn_main_boilerplate_from_formatted, stub_message,
+		GenericHelp, ResultHandling,
 	},
 };
 
+/// Most seeds ?miri runs with at once, since every seed is a separate Miri run
+const MAX_MIRI_SEEDS: u32 = 16;
+
+fn relevant_miri_output(stderr: &str) -> String {
+	extract_relevant_lines(stderr, &["Running `/playground"], &["error: aborting"]).to_owned()
+}
+
+/// Combines the results of running Miri with different seeds into one, which lists the seeds that
+/// failed and shows the output of the first of them
+fn merge_seed_results(seeds: &[u64], results: Vec<PlayResult>) -> PlayResult {
+	let range = format!("{}..={}", seeds[0], seeds[seeds.len() - 1]);
+	let failed = seeds
+		.iter()
+		.zip(&results)
+		.filter(|(_, result)| !result.success)
+		.map(|(seed, _)| seed.to_string())
+		.collect::<Vec<_>>();
+
+	let first_failure = results.iter().position(|result| !result.success);
+	let index = first_failure.unwrap_or(0);
+	let result = results
+		.into_iter()
+		.nth(index)
+		.expect("there's a result for every seed");
+
+	if first_failure.is_none() {
+		return PlayResult {
+			success: true,
+			stdout: result.stdout,
+			stderr: format!(
+				"No seed in {range} failed\n{}",
+				relevant_miri_output(&result.stderr)
+			),
+		};
+	}
+	PlayResult {
+		success: false,
+		stdout: result.stdout,
+		stderr: format!(
+			"Seeds that failed, out of {}: {}\nOutput with seed {}:\n{}",
+			range,
+			failed.join(", "),
+			seeds[index],
+			relevant_miri_output(&result.stderr)
+		),
+	}
+}
+
+/// Runs the code in Miri like ?miri does. Returns the code as it was sent to the playground,
+/// along with the result
+pub async fn run_miri(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<(String, PlayResult), Error> {
+	if !(1..=MAX_MIRI_SEEDS).contains(&flags.seeds) {
+		bail!("`seeds` must be between 1 and {}", MAX_MIRI_SEEDS);
+	}
+	if flags.seeds > 1 && !ctx.data().playground.supports_miri_flags() {
+		bail!("`seeds` needs a local sandbox, play.rust-lang.org only runs Miri with seed 0");
+	}
+
+	// Concurrency bugs often only show with some thread interleavings, which depend on the seed
+	let seeds = (0..flags.seeds)
+		.map(|i| flags.seed.checked_add(u64::from(i)))
+		.collect::<Option<Vec<_>>>()
+		.ok_or_else(|| anyhow!("`seed` is too large to run with {} seeds", flags.seeds))?;
+
+	let full_code = maybe_wrapped(
+		code,
+		ResultHandling::Discard,
+		ctx.prefix().contains("Sweat"),
+		false,
+		flags.runtime,
+	);
+
+	let request = |seed| MiriRequest {
+		code: &full_code,
+		edition: flags.edition,
+		aliasing_model: flags.aliasing,
+		strict_provenance: flags.strict_provenance,
+		symbolic_alignment: flags.symbolic_alignment,
+		seed,
+	};
+	let run = async {
+		let playground = &ctx.data().playground;
+		if flags.seeds == 1 {
+			let mut result = playground.miri(&request(flags.seed)).await?;
+			result.stderr = relevant_miri_output(&result.stderr);
+			return Ok(result);
+		}
+
+		let requests = seeds.iter().map(|&seed| request(seed)).collect::<Vec<_>>();
+
+		// Every seed that runs at the same time needs its own slot. The first one runs on the slot
+		// of this command, the others only on slots that are free right now, because queueing for
+		// more while holding one could deadlock
+		let scheduler = &ctx.data().scheduler;
+		let extra_permits = std::iter::from_fn(|| {
+			scheduler.try_acquire(ctx.author().id, crate::scheduler::Service::Playground)
+		})
+		.take(requests.len() - 1)
+		.collect::<Vec<_>>();
+		let chunk_size = requests.len().div_ceil(extra_permits.len() + 1);
+		let results = futures_util::future::try_join_all(requests.chunks(chunk_size).map(
+			|chunk| async move {
+				let mut results = Vec::new();
+				for request in chunk {
+					results.push(playground.miri(request).await?);
+				}
+				Ok::<_, Error>(results)
+			},
+		))
+		.await?
+		.into_iter()
+		.flatten()
+		.collect();
+		drop(extra_permits);
+		Ok(merge_seed_results(&seeds, results))
+	};
+
+	let result = run_on_playground(ctx, "miri", code, &full_code, flags, run).await?;
+
+	Ok((full_code.into_owned(), result))
+}
+
 /// Run code and detect undefined behavior using Miri
 #[poise::command(
 	prefix_command,
@@ -26,38 +155,24 @@ use super::{
 pub async fn miri(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
 	ctx.say(stub_message(ctx)).await?;
-	let code = &maybe_wrapped(
-		&code.code,
-		ResultHandling::Discard,
-		ctx.prefix().contains("Sweat"),
-		false,
-	);
-	let (flags, flag_parse_errors) = parse_flags(flags);
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
 
-	let mut result: PlayResult = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/miri")
-		.json(&MiriRequest {
-			code,
-			edition: flags.edition,
-		})
-		.send()
-		.await?
-		.json()
-		.await?;
+	let (full_code, result) = run_miri(ctx, &flags, &code).await?;
 
-	result.stderr = extract_relevant_lines(
-		&result.stderr,
-		&["Running `/playground"],
-		&["error: aborting"],
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_miri(ctx, &flags, code).await?.1) }),
 	)
-	.to_owned();
-
-	send_reply(ctx, result, code, &flags, &flag_parse_errors).await
+	.await
 }
 
 #[must_use]
@@ -65,16 +180,68 @@ pub fn miri_help() -> String {
 	generic_help(GenericHelp {
 		command: "miri",
 		desc: "Execute this program in the Miri interpreter to detect certain cases of undefined \
-        behavior (like out-of-bounds memory access)",
-		mode_and_channel: false,
+        behavior (like out-of-bounds memory access). With `seeds=N`, Miri runs with N different \
+        seeds at once and reports which of them failed. Seeds other than 0 need a local sandbox, \
+        play.rust-lang.org doesn't support them",
 		// Playgrounds sends miri warnings/errors and output in the same field so we can't filter
 		// warnings out
-		warn: false,
-		run: false,
+		flags: &[
+			"runtime",
+			"aliasing",
+			"strict_provenance",
+			"symbolic_alignment",
+			"seed",
+			"seeds",
+		],
 		example_code: "code",
 	})
 }
 
+/// Expands the macros in the code like ?expand does. Returns the code as it was sent to the
+/// playground, along with the result
+pub async fn run_expand(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<(String, PlayResult), Error> {
+	let full_code = maybe_wrap(code, ResultHandling::None);
+	let was_fn_main_wrapped = matches!(full_code, Cow::Owned(_));
+
+	let mut result = run_on_playground(ctx, "expand", code, &full_code, flags, async {
+		let mut result = ctx
+			.data()
+			.playground
+			.macro_expansion(&MacroExpansionRequest {
+				code: &full_code,
+				edition: flags.edition,
+			})
+			.await?;
+
+		result.stderr = extract_relevant_lines(
+			&result.stderr,
+			&["Finished ", "Compiling playground"],
+			&["error: aborting"],
+		)
+		.to_owned();
+
+		if result.success {
+			match apply_online_rustfmt(ctx, &result.stdout, flags.edition).await {
+				Ok(PlayResult { success: true, stdout, .. }) => result.stdout = stdout,
+				Ok(PlayResult { success: false, stderr, .. }) => warn!("Huh, rustfmt failed even though this code successfully passed through macro expansion before: {}", stderr),
+				Err(e) => warn!("Couldn't run rustfmt: {}", e),
+			}
+		}
+		Ok::<_, Error>(result)
+	})
+	.await?;
+
+	if was_fn_main_wrapped {
+		result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
+	}
+
+	Ok((full_code.into_owned(), result))
+}
+
 /// Expand macros to their raw desugared form
 #[poise::command(
 	prefix_command,
@@ -85,46 +252,24 @@ pub fn miri_help() -> String {
 pub async fn expand(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
 	ctx.say(stub_message(ctx)).await?;
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
 
-	let code = maybe_wrap(&code.code, ResultHandling::None);
-	let was_fn_main_wrapped = matches!(code, Cow::Owned(_));
-	let (flags, flag_parse_errors) = parse_flags(flags);
+	let (full_code, result) = run_expand(ctx, &flags, &code).await?;
 
-	let mut result: PlayResult = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/macro-expansion")
-		.json(&MacroExpansionRequest {
-			code: &code,
-			edition: flags.edition,
-		})
-		.send()
-		.await?
-		.json()
-		.await?;
-
-	result.stderr = extract_relevant_lines(
-		&result.stderr,
-		&["Finished ", "Compiling playground"],
-		&["error: aborting"],
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_expand(ctx, &flags, code).await?.1) }),
 	)
-	.to_owned();
-
-	if result.success {
-		match apply_online_rustfmt(ctx, &result.stdout, flags.edition).await {
-			Ok(PlayResult { success: true, stdout, .. }) => result.stdout = stdout,
-			Ok(PlayResult { success: false, stderr, .. }) => warn!("Huh, rustfmt failed even though this code successfully passed through macro expansion before: {}", stderr),
-			Err(e) => warn!("Couldn't run rustfmt: {}", e),
-		}
-	}
-	if was_fn_main_wrapped {
-		result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
-	}
-
-	send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+	.await
 }
 
 #[must_use]
@@ -132,13 +277,89 @@ pub fn expand_help() -> String {
 	generic_help(GenericHelp {
 		command: "expand",
 		desc: "Expand macros to their raw desugared form",
-		mode_and_channel: false,
-		warn: false,
-		run: false,
+		flags: &[],
 		example_code: "code",
 	})
 }
 
+/// Crate attributes that enable the lints of the `lints` flag. All on one line, so that the line
+/// numbers in the lints don't change
+fn lint_attributes(lints: &[String]) -> String {
+	if lints.is_empty() {
+		return String::new();
+	}
+	let lint_list = lints
+		.iter()
+		.map(|lint| format!("clippy::{lint}"))
+		.collect::<Vec<_>>()
+		.join(", ");
+	let mut attributes = format!("#![warn({lint_list})] ");
+	if lints.iter().any(|lint| lint == "restriction") {
+		// Clippy discourages enabling the whole restriction group, which is fine to try things out
+		attributes += "#![allow(clippy::blanket_clippy_restriction_lints)] ";
+	}
+	attributes
+}
+
+/// Lints the code like ?clippy does. Returns the code as it was sent to the playground, along
+/// with the result
+pub async fn run_clippy(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<(String, PlayResult), Error> {
+	// dead_code: https://github.com/kangalioo/rustbot/issues/44
+	// let_unit_value: silence warning about `let _ = { ... }` wrapper that swallows return val,
+	// unless the lint was asked for by name
+	let allows = if flags.lints.iter().any(|lint| lint == "let_unit_value") {
+		"#![allow(dead_code)] "
+	} else {
+		"#![allow(dead_code, clippy::let_unit_value)] "
+	};
+	let full_code = format!(
+		// The lint attributes go first, so that the allows take precedence for the wrapper code
+		"{}{allows}{}",
+		lint_attributes(&flags.lints),
+		maybe_wrapped(
+			code,
+			ResultHandling::Discard,
+			ctx.prefix().contains("Sweat"),
+			false,
+			flags.runtime,
+		)
+	);
+
+	let mut result = run_on_playground(
+		ctx,
+		"clippy",
+		code,
+		&full_code,
+		flags,
+		ctx.data().playground.clippy(&ClippyRequest {
+			code: &full_code,
+			edition: flags.edition,
+			crate_type: CrateType::Binary,
+		}),
+	)
+	.await?;
+
+	result.stderr = render_lints(&result.stderr).unwrap_or_else(|| {
+		extract_relevant_lines(
+			&result.stderr,
+			&["Checking playground", "Running `/playground"],
+			&[
+				"error: aborting",
+				"1 warning emitted",
+				"warnings emitted",
+				"Finished ",
+			],
+		)
+		.to_owned()
+	});
+
+	Ok((full_code, result))
+}
+
 /// Catch common mistakes using the Clippy linter
 #[poise::command(
 	prefix_command,
@@ -149,64 +370,65 @@ pub fn expand_help() -> String {
 pub async fn clippy(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
 	ctx.say(stub_message(ctx)).await?;
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
 
-	let code = &format!(
-		// dead_code: https://github.com/kangalioo/rustbot/issues/44
-		// let_unit_value: silence warning about `let _ = { ... }` wrapper that swallows return val
-		"#![allow(dead_code, clippy::let_unit_value)] {}",
-		maybe_wrapped(
-			&code.code,
-			ResultHandling::Discard,
-			ctx.prefix().contains("Sweat"),
-			false,
-		)
-	);
-	let (flags, flag_parse_errors) = parse_flags(flags);
+	let (full_code, result) = run_clippy(ctx, &flags, &code).await?;
 
-	let mut result: PlayResult = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/clippy")
-		.json(&ClippyRequest {
-			code,
-			edition: flags.edition,
-			crate_type: CrateType::Binary,
-		})
-		.send()
-		.await?
-		.json()
-		.await?;
-
-	result.stderr = extract_relevant_lines(
-		&result.stderr,
-		&["Checking playground", "Running `/playground"],
-		&[
-			"error: aborting",
-			"1 warning emitted",
-			"warnings emitted",
-			"Finished ",
-		],
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_clippy(ctx, &flags, code).await?.1) }),
 	)
-	.to_owned();
-
-	send_reply(ctx, result, code, &flags, &flag_parse_errors).await
+	.await
 }
 
 #[must_use]
 pub fn clippy_help() -> String {
 	generic_help(GenericHelp {
 		command: "clippy",
-		desc: "Catch common mistakes and improve the code using the Clippy linter",
-		mode_and_channel: false,
-		warn: false,
-		run: false,
+		desc: "Catch common mistakes and improve the code using the Clippy linter. The lints are \
+listed by lint, with a link to the documentation of the lint. `dead_code` is always allowed, and \
+`clippy::let_unit_value` unless `lints` names it, because the code around snippets triggers them",
+		flags: &["lints", "runtime"],
 		example_code: "code",
 	})
 }
 
+/// Formats the code like ?fmt does. Returns the code as it was sent to the playground, along
+/// with the result
+pub async fn run_fmt(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<(String, PlayResult), Error> {
+	let full_code = maybe_wrap(code, ResultHandling::None);
+	let was_fn_main_wrapped = matches!(full_code, Cow::Owned(_));
+
+	let mut result = run_on_playground(
+		ctx,
+		"fmt",
+		code,
+		&full_code,
+		flags,
+		apply_online_rustfmt(ctx, &full_code, flags.edition),
+	)
+	.await?;
+
+	if was_fn_main_wrapped {
+		result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
+	}
+
+	Ok((full_code.into_owned(), result))
+}
+
 /// Format code using rustfmt
 #[poise::command(
 	prefix_command,
@@ -217,21 +439,24 @@ pub fn clippy_help() -> String {
 pub async fn fmt(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
 	ctx.say(stub_message(ctx)).await?;
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
 
-	let code = &maybe_wrap(&code.code, ResultHandling::None);
-	let was_fn_main_wrapped = matches!(code, Cow::Owned(_));
-	let (flags, flag_parse_errors) = parse_flags(flags);
-
-	let mut result = apply_online_rustfmt(ctx, code, flags.edition).await?;
+	let (full_code, result) = run_fmt(ctx, &flags, &code).await?;
 
-	if was_fn_main_wrapped {
-		result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
-	}
-
-	send_reply(ctx, result, code, &flags, &flag_parse_errors).await
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_fmt(ctx, &flags, code).await?.1) }),
+	)
+	.await
 }
 
 #[must_use]
@@ -239,9 +464,7 @@ pub fn fmt_help() -> String {
 	generic_help(GenericHelp {
 		command: "fmt",
 		desc: "Format code using rustfmt",
-		mode_and_channel: false,
-		warn: false,
-		run: false,
+		flags: &[],
 		example_code: "code",
 	})
 }
@@ -1,57 +1,105 @@
+use std::borrow::Cow;
+
 use anyhow::Error;
 
+use crate::helpers::code_block_or_attachment;
 use crate::types::Context;
 
 use super::{
-	api::{CrateType, PlayResult, PlaygroundRequest},
+	api::{CommandFlags, CrateType, PlayResult, PlaygroundRequest},
 	util::{
-		format_play_eval_stderr, generic_help, maybe_wrapped, parse_flags, send_reply,
-		stub_message, GenericHelp, ResultHandling,
+		execute_with_live_output, format_play_eval_stderr, generic_help, maybe_wrapped,
+		parse_flags_with_code, run_on_playground, send_reply, GenericHelp, ResultHandling,
 	},
 };
 
+/// Runs the code like ?play or ?eval do. Returns the code as it was sent to the playground,
+/// along with the result
+pub async fn run_play_or_eval(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+	result_handling: ResultHandling,
+) -> Result<(String, PlayResult), Error> {
+	let result_handling = match result_handling {
+		ResultHandling::Print if flags.repl => ResultHandling::Repl,
+		result_handling => result_handling,
+	};
+	// Libraries and tests don't run `main`, so there's nothing to wrap the code in
+	let full_code = if flags.tests || matches!(flags.crate_type, Some(CrateType::Library)) {
+		Cow::Borrowed(code)
+	} else {
+		maybe_wrapped(
+			code,
+			result_handling,
+			ctx.prefix().contains("Sweat"),
+			ctx.prefix().contains("OwO") || ctx.prefix().contains("Cat"),
+			flags.runtime,
+		)
+	};
+
+	let mut result = run_on_playground(
+		ctx,
+		match result_handling {
+			ResultHandling::Print | ResultHandling::Repl => "eval",
+			_ => "play",
+		},
+		code,
+		&full_code,
+		flags,
+		execute_with_live_output(
+			ctx,
+			&PlaygroundRequest {
+				code: &full_code,
+				channel: flags.channel,
+				crate_type: flags.crate_type.unwrap_or(CrateType::Binary),
+				edition: flags.edition,
+				mode: flags.mode,
+				tests: flags.tests,
+				backtrace: flags.backtrace,
+			},
+		),
+	)
+	.await?;
+
+	result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
+
+	Ok((full_code.into_owned(), result))
+}
+
 // play and eval work similarly, so this function abstracts over the two
 async fn play_or_eval(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
 	force_warnings: bool, // If true, force enable warnings regardless of flags
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 	result_handling: ResultHandling,
 ) -> Result<(), Error> {
-	ctx.say(stub_message(ctx)).await?;
-
-	let code = maybe_wrapped(
-		&code.code,
-		result_handling,
-		ctx.prefix().contains("Sweat"),
-		ctx.prefix().contains("OwO") || ctx.prefix().contains("Cat"),
-	);
-	let (mut flags, flag_parse_errors) = parse_flags(flags);
+	let code = code_block_or_attachment(ctx, code, 0).await?;
+	let (mut flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
 
 	if force_warnings {
 		flags.warn = true;
 	}
 
-	let mut result: PlayResult = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/execute")
-		.json(&PlaygroundRequest {
-			code: &code,
-			channel: flags.channel,
-			crate_type: CrateType::Binary,
-			edition: flags.edition,
-			mode: flags.mode,
-			tests: false,
-		})
-		.send()
-		.await?
-		.json()
-		.await?;
+	let (full_code, result) = run_play_or_eval(ctx, &flags, &code, result_handling).await?;
 
-	result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
-
-	send_reply(ctx, result, &code, &flags, &flag_parse_errors).await
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| {
+			Box::pin(async move {
+				Ok(run_play_or_eval(ctx, &flags, code, result_handling)
+					.await?
+					.1)
+			})
+		},
+	)
+	.await
 }
 
 /// Compile and run Rust code in a playground
@@ -64,7 +112,7 @@ async fn play_or_eval(
 pub async fn play(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
 	play_or_eval(ctx, flags, false, code, ResultHandling::None).await
 }
@@ -74,9 +122,15 @@ pub fn play_help() -> String {
 	generic_help(GenericHelp {
 		command: "play",
 		desc: "Compile and run Rust code",
-		mode_and_channel: true,
-		warn: true,
-		run: false,
+		flags: &[
+			"mode",
+			"channel",
+			"warn",
+			"runtime",
+			"crate_type",
+			"tests",
+			"backtrace",
+		],
 		example_code: "code",
 	})
 }
@@ -91,7 +145,7 @@ category = "Playground"
 pub async fn playwarn(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
 	play_or_eval(ctx, flags, true, code, ResultHandling::None).await
 }
@@ -101,9 +155,14 @@ pub fn playwarn_help() -> String {
 	generic_help(GenericHelp {
 		command: "playwarn",
 		desc: "Compile and run Rust code with warnings. Equivalent to `?play warn=true`",
-		mode_and_channel: true,
-		warn: false,
-		run: false,
+		flags: &[
+			"mode",
+			"channel",
+			"runtime",
+			"crate_type",
+			"tests",
+			"backtrace",
+		],
 		example_code: "code",
 	})
 }
@@ -118,7 +177,7 @@ pub fn playwarn_help() -> String {
 pub async fn eval(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	code: poise::CodeBlock,
+	code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
 	play_or_eval(ctx, flags, false, code, ResultHandling::Print).await
 }
@@ -127,10 +186,9 @@ pub async fn eval(
 pub fn eval_help() -> String {
 	generic_help(GenericHelp {
 		command: "eval",
-		desc: "Compile and run Rust code",
-		mode_and_channel: true,
-		warn: true,
-		run: false,
+		desc: "Compile and run Rust code and print the result. With `repl=true`, every top-level \
+			expression is printed along with its source, like in a REPL session",
+		flags: &["mode", "channel", "warn", "runtime", "backtrace", "repl"],
 		example_code: "code",
 	})
 }
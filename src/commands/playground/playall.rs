@@ -0,0 +1,206 @@
+use std::fmt::Write as _;
+
+use anyhow::Error;
+
+use crate::helpers::{code_block_or_attachment, send_long_output, LongOutput};
+use crate::types::Context;
+
+use super::{
+	api::{Across, Channel, CommandFlags, CrateType, Edition, PlayResult, PlaygroundRequest},
+	util::{
+		format_play_eval_stderr, generic_help, maybe_wrapped, parse_flags_with_code,
+		run_on_playground, stub_message, GenericHelp, ResultHandling,
+	},
+};
+
+/// Lines beyond this are ignored for diffing, to keep the diff cheap
+const MAX_DIFF_LINES: usize = 300;
+
+/// Line-based diff from `old` to `new` that only lists the lines that differ
+fn line_diff(old: &str, new: &str) -> String {
+	let old = old.lines().take(MAX_DIFF_LINES).collect::<Vec<_>>();
+	let new = new.lines().take(MAX_DIFF_LINES).collect::<Vec<_>>();
+
+	// longest_common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+	let mut longest_common = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+	for i in (0..old.len()).rev() {
+		for j in (0..new.len()).rev() {
+			longest_common[i][j] = if old[i] == new[j] {
+				longest_common[i + 1][j + 1] + 1
+			} else {
+				longest_common[i + 1][j].max(longest_common[i][j + 1])
+			};
+		}
+	}
+
+	let mut diff = String::new();
+	let (mut i, mut j) = (0, 0);
+	while i < old.len() && j < new.len() {
+		if old[i] == new[j] {
+			i += 1;
+			j += 1;
+		} else if longest_common[i + 1][j] >= longest_common[i][j + 1] {
+			let _ = writeln!(diff, "- {}", old[i]);
+			i += 1;
+		} else {
+			let _ = writeln!(diff, "+ {}", new[j]);
+			j += 1;
+		}
+	}
+	for line in &old[i..] {
+		let _ = writeln!(diff, "- {line}");
+	}
+	for line in &new[j..] {
+		let _ = writeln!(diff, "+ {line}");
+	}
+
+	diff
+}
+
+/// Run code on all channels or editions at once and compare the outputs
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "playall_help",
+	category = "Playground"
+)]
+pub async fn playall(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
+	ctx.say(stub_message(ctx)).await?;
+
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
+
+	let full_code = maybe_wrapped(&code, ResultHandling::None, false, false, flags.runtime);
+	let result = run_on_playground(
+		ctx,
+		"playall",
+		&code,
+		&full_code,
+		&flags,
+		run_playall(ctx, &flags, &full_code),
+	)
+	.await?;
+
+	let output = LongOutput {
+		header: &flag_parse_errors,
+		blocks: vec![("diff", &result.stdout)],
+		footer: "",
+	};
+	let mut reply =
+		send_long_output(ctx, &output, flags.output, async { None }, vec![], vec![]).await?;
+	// Without extra buttons, this only handles the page buttons until they time out
+	reply.next_interaction(ctx).await?;
+	Ok(())
+}
+
+/// Runs the code on every channel or edition and compares the outputs. The comparison is the
+/// stdout of the result, as the contents of a `diff` code block
+async fn run_playall(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<PlayResult, Error> {
+	let runs = match flags.across {
+		Across::Channels => Channel::ALL
+			.iter()
+			.map(|&channel| (channel.as_str(), channel, flags.edition))
+			.collect::<Vec<_>>(),
+		Across::Editions => Edition::ALL
+			.iter()
+			.map(|&edition| (edition.as_str(), flags.channel, edition))
+			.collect::<Vec<_>>(),
+	};
+	let requests = runs
+		.iter()
+		.map(|&(_, channel, edition)| PlaygroundRequest {
+			code,
+			channel,
+			crate_type: CrateType::Binary,
+			edition,
+			mode: flags.mode,
+			tests: false,
+			backtrace: flags.backtrace,
+		})
+		.collect::<Vec<_>>();
+
+	// The runs are few and short, so they share the single slot of the command
+	let results = futures_util::future::join_all(
+		requests
+			.iter()
+			.map(|request| ctx.data().playground.execute(request)),
+	)
+	.await;
+
+	// Group runs with identical output together, in the order they were listed
+	let mut success = true;
+	let mut groups: Vec<(Vec<&str>, String)> = Vec::new();
+	for (&(label, ..), result) in runs.iter().zip(results) {
+		let output = match result {
+			Ok(result) => {
+				success &= result.success;
+				crate::helpers::merge_output_and_errors(
+					&result.stdout,
+					&format_play_eval_stderr(&result.stderr, flags.warn),
+				)
+				.into_owned()
+			}
+			Err(e) => {
+				success = false;
+				format!("Failed to run: {e}")
+			}
+		}
+		.replace('`', "\u{200b}`");
+
+		match groups.iter_mut().find(|(_, o)| *o == output) {
+			Some((labels, _)) => labels.push(label),
+			None => groups.push((vec![label], output)),
+		}
+	}
+
+	// The labels are hunk headers, and the output of the first group is indented like the
+	// unchanged lines of a diff, so that only the differences are highlighted
+	let mut comparison = String::new();
+	let (reference_labels, reference_output) = &groups[0];
+	if groups.len() == 1 {
+		let _ = writeln!(
+			comparison,
+			"@@ {}: all the same @@",
+			reference_labels.join(", ")
+		);
+	} else {
+		let _ = writeln!(comparison, "@@ {} @@", reference_labels.join(", "));
+	}
+	for line in reference_output.lines() {
+		let _ = writeln!(comparison, "  {line}");
+	}
+	for (labels, output) in &groups[1..] {
+		let _ = writeln!(
+			comparison,
+			"@@ {}, diff against {} @@",
+			labels.join(", "),
+			reference_labels[0]
+		);
+		comparison += &line_diff(reference_output, output);
+	}
+
+	Ok(PlayResult {
+		success,
+		stdout: comparison,
+		stderr: String::new(),
+	})
+}
+
+#[must_use]
+pub fn playall_help() -> String {
+	generic_help(GenericHelp {
+		command: "playall",
+		desc: "Run code on the stable, beta and nightly channels at the same time and compare the \
+		outputs. With `across=editions`, runs on every edition instead",
+		flags: &["mode", "channel", "across", "warn", "backtrace"],
+		example_code: "code",
+	})
+}
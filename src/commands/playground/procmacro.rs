@@ -1,11 +1,19 @@
+use std::borrow::Cow;
+
 use anyhow::Error;
+use tracing::warn;
 
+use crate::helpers::code_block_or_attachment;
 use crate::types::Context;
 
 use super::{
-	api::{Channel, CrateType, Edition, Mode, PlayResult, PlaygroundRequest},
+	api::{
+		apply_online_rustfmt, Channel, CommandFlags, CrateType, Edition, Mode, PlayResult,
+		PlaygroundRequest,
+	},
 	util::{
-		format_play_eval_stderr, generic_help, maybe_wrap, parse_flags, send_reply, stub_message,
+		format_play_eval_stderr, generic_help, maybe_wrap, parse_flags_with_code,
+		run_on_playground, send_reply, strip_fn_main_boilerplate_from_formatted, stub_message,
 		GenericHelp, ResultHandling,
 	},
 };
@@ -20,22 +28,67 @@ use super::{
 pub async fn procmacro(
 	ctx: Context<'_>,
 	flags: poise::KeyValueArgs,
-	macro_code: poise::CodeBlock,
-	usage_code: poise::CodeBlock,
+	macro_code: Option<poise::CodeBlock>,
+	usage_code: Option<poise::CodeBlock>,
 ) -> Result<(), Error> {
+	// With attachments instead of code blocks, the first file is the macro and the second the usage
+	let attachment_index = usize::from(macro_code.is_none());
+	let macro_code = code_block_or_attachment(ctx, macro_code, 0).await?;
+	let usage_code = code_block_or_attachment(ctx, usage_code, attachment_index).await?;
 	ctx.say(stub_message(ctx)).await?;
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &macro_code);
+
+	let (full_code, result) = run_procmacro(ctx, &flags, &macro_code, &usage_code).await?;
+
+	let macro_code: &str = &macro_code;
+	let usage_code: &str = &usage_code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| {
+			Box::pin(async move { Ok(run_procmacro(ctx, &flags, macro_code, usage_code).await?.1) })
+		},
+	)
+	.await
+}
+
+/// Separates the macro code from the usage code when both are stored as one snippet, e.g. in the
+/// command history
+const PROCMACRO_SEPARATOR: &str = "\n// ---- usage ----\n";
+
+#[must_use]
+pub fn join_procmacro_code(macro_code: &str, usage_code: &str) -> String {
+	format!("{macro_code}{PROCMACRO_SEPARATOR}{usage_code}")
+}
 
-	let macro_code = macro_code.code;
-	let usage_code = maybe_wrap(&usage_code.code, ResultHandling::None);
+/// Inverse of [`join_procmacro_code`]. Returns the macro code and the usage code
+#[must_use]
+pub fn split_procmacro_code(code: &str) -> Option<(&str, &str)> {
+	code.split_once(PROCMACRO_SEPARATOR)
+}
 
-	let (flags, flag_parse_errors) = parse_flags(flags);
+/// Compiles the proc macro and the code using it. Returns the code as it was sent to the
+/// playground, along with the result
+pub async fn run_procmacro(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	macro_code: &str,
+	usage_code: &str,
+) -> Result<(String, PlayResult), Error> {
+	let wrapped_usage_code = maybe_wrap(usage_code, ResultHandling::None);
 
 	let mut generated_code = format!(
 		stringify!(
 			const MACRO_CODE: &str = r#####"{}"#####;
 			const USAGE_CODE: &str = r#####"{}"#####;
+			const EDITION: &str = "{}";
 		),
-		macro_code, usage_code
+		macro_code,
+		wrapped_usage_code,
+		flags.edition.as_str()
 	);
 	generated_code += r#"
 pub fn cmd_run(cmd: &str) {
@@ -56,28 +109,50 @@ pub fn cmd_stdout(cmd: &str) -> String {
     String::from_utf8(output.stdout).unwrap()
 }
 
+const CARGO_TOML: &str = "
+[package]
+name = \"procmacro\"
+version = \"0.1.0\"
+edition = \"EDITION\"
+
+[lib]
+proc-macro = true
+
+[dependencies]
+proc-macro2 = \"1\"
+quote = \"1\"
+syn = { version = \"2\", features = [\"full\", \"extra-traits\", \"visit\", \"visit-mut\", \"fold\"] }
+";
+
 fn main() -> std::io::Result<()> {
-    use std::io::Write as _;
+    // The playground project has the vendored crates in its lockfile. Copying it makes cargo
+    // pick those versions, which are available offline
+    let playground_lockfile = std::env::current_dir()?.join("Cargo.lock");
     std::env::set_current_dir(cmd_stdout("mktemp -d").trim())?;
     cmd_run("cargo init -q --name procmacro --lib");
     std::fs::write("src/lib.rs", MACRO_CODE)?;
     std::fs::write("src/main.rs", USAGE_CODE)?;
-    std::fs::OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("Cargo.toml")?
-        .write_all(b"[lib]\nproc-macro = true")?;
+    std::fs::write("Cargo.toml", CARGO_TOML.replace("EDITION", EDITION))?;
+    let _ = std::fs::copy(playground_lockfile, "Cargo.lock");
     cmd_run("cargo"#;
-	generated_code += if flags.run { " r" } else { " c" };
-	generated_code += r#" -q --bin procmacro");
+	generated_code += if flags.expand {
+		" rustc -q --offline --bin procmacro --profile=check -- -Zunpretty=expanded"
+	} else if flags.run {
+		" run -q --offline --bin procmacro"
+	} else {
+		" check -q --offline --bin procmacro"
+	};
+	generated_code += r#"");
     Ok(())
 }"#;
 
-	let mut result: PlayResult = ctx
-		.data()
-		.http
-		.post("https://play.rust-lang.org/execute")
-		.json(&PlaygroundRequest {
+	let mut result = run_on_playground(
+		ctx,
+		"procmacro",
+		&join_procmacro_code(macro_code, usage_code),
+		&generated_code,
+		flags,
+		ctx.data().playground.execute(&PlaygroundRequest {
 			code: &generated_code,
 			channel: Channel::Nightly, // so that inner proc macro gets nightly too
 			// These flags only apply to the glue code
@@ -85,11 +160,10 @@ fn main() -> std::io::Result<()> {
 			edition: Edition::E2024,
 			mode: Mode::Debug,
 			tests: false,
-		})
-		.send()
-		.await?
-		.json()
-		.await?;
+			backtrace: flags.backtrace,
+		}),
+	)
+	.await?;
 
 	// funky
 	result.stderr = format_play_eval_stderr(
@@ -97,7 +171,26 @@ fn main() -> std::io::Result<()> {
 		flags.warn,
 	);
 
-	send_reply(ctx, result, &generated_code, &flags, &flag_parse_errors).await
+	if flags.expand && result.success {
+		match apply_online_rustfmt(ctx, &result.stdout, flags.edition).await {
+			Ok(PlayResult {
+				success: true,
+				stdout,
+				..
+			}) => result.stdout = stdout,
+			Ok(PlayResult {
+				success: false,
+				stderr,
+				..
+			}) => warn!("rustfmt failed on the expanded usage code: {}", stderr),
+			Err(e) => warn!("Couldn't run rustfmt: {}", e),
+		}
+		if matches!(wrapped_usage_code, Cow::Owned(_)) {
+			result.stdout = strip_fn_main_boilerplate_from_formatted(&result.stdout);
+		}
+	}
+
+	Ok((generated_code, result))
 }
 
 #[must_use]
@@ -107,11 +200,10 @@ pub fn procmacro_help() -> String {
 		desc: "\
 Compiles a procedural macro by providing two snippets: one for the \
 proc-macro code, and one for the usage code which can refer to the proc-macro crate as \
-`procmacro`. By default, the code is only compiled, _not run_! To run the final code too, pass
-`run=true`.",
-		mode_and_channel: false,
-		warn: true,
-		run: true,
+`procmacro`. The proc-macro crate can use `syn`, `quote` and `proc-macro2`. By default, the \
+code is only compiled, _not run_! To run the final code too, pass `run=true`. To see the usage \
+code after macro expansion, pass `expand=true`. The edition applies to both crates.",
+		flags: &["warn", "run", "expand", "backtrace"],
 		example_code: "
 #[proc_macro]
 pub fn foo(_: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -0,0 +1,422 @@
+use anyhow::Error;
+
+use crate::cache::unix_now;
+use crate::helpers::{code_block_or_attachment, send_long_output, LongOutput, OutputMode};
+use crate::types::Context;
+
+use super::{
+	api::{CommandFlags, CrateType, PlayResult, PlaygroundRequest},
+	util::{
+		execute_with_live_output, format_play_eval_stderr, generic_help, maybe_wrapped,
+		parse_flags_with_code, run_on_playground, send_reply, GenericHelp, ResultHandling,
+	},
+};
+
+/// Sessions are forgotten after this many seconds without a ?repl invocation
+const SESSION_TIMEOUT: i64 = 60 * 60;
+
+/// The definitions that the REPL session of a channel remembers
+#[derive(Default)]
+struct Session {
+	/// Crate attributes, one per line
+	attributes: String,
+	/// Items, separated by empty lines
+	items: String,
+}
+
+/// A ?repl snippet, split into the parts that are remembered and the rest
+struct Snippet<'a> {
+	attributes: Vec<&'a str>,
+	/// Items along with their name, if they have one
+	items: Vec<(Option<String>, &'a str)>,
+	/// The code after the crate attributes
+	body: &'a str,
+}
+
+fn parse_statements(
+	input: syn::parse::ParseStream<'_>,
+) -> syn::Result<(Vec<syn::Attribute>, Vec<syn::Stmt>)> {
+	Ok((
+		syn::Attribute::parse_inner(input)?,
+		syn::Block::parse_within(input)?,
+	))
+}
+
+/// Name of an item, if defining another item with the same name would be an error
+fn item_name(item: &syn::Item) -> Option<String> {
+	use syn::Item;
+
+	let ident = match item {
+		Item::Const(item) => &item.ident,
+		Item::Enum(item) => &item.ident,
+		Item::Fn(item) => &item.sig.ident,
+		Item::Macro(item) => item.ident.as_ref()?,
+		Item::Mod(item) => &item.ident,
+		Item::Static(item) => &item.ident,
+		Item::Struct(item) => &item.ident,
+		Item::Trait(item) => &item.ident,
+		Item::TraitAlias(item) => &item.ident,
+		Item::Type(item) => &item.ident,
+		Item::Union(item) => &item.ident,
+		_ => return None,
+	};
+	Some(ident.to_string()).filter(|name| name != "_")
+}
+
+/// Splits the code into crate attributes, items and the rest. If syn can't parse the code,
+/// nothing is remembered of it
+fn parse_snippet(code: &str) -> Snippet<'_> {
+	use syn::{parse::Parser, spanned::Spanned, Item, Stmt};
+
+	let Ok((attributes, stmts)) = parse_statements.parse_str(code) else {
+		proc_macro2::extra::invalidate_current_thread_spans();
+		return Snippet {
+			attributes: vec![],
+			items: vec![],
+			body: code,
+		};
+	};
+
+	let items = stmts
+		.iter()
+		.filter_map(|stmt| match stmt {
+			// A main function means the code isn't wrapped, so there's nothing to splice it into
+			Stmt::Item(Item::Fn(item)) if item.sig.ident == "main" => None,
+			Stmt::Item(item) => Some((item_name(item), &code[item.span().byte_range()])),
+			_ => None,
+		})
+		.collect();
+	let body_start = attributes
+		.last()
+		.map_or(0, |attribute| attribute.span().byte_range().end);
+
+	let attributes = attributes
+		.iter()
+		.map(|attribute| &code[attribute.span().byte_range()])
+		.collect();
+	// The source locations aren't needed anymore, see `maybe_wrapped`
+	proc_macro2::extra::invalidate_current_thread_spans();
+
+	Snippet {
+		attributes,
+		items,
+		body: &code[body_start..],
+	}
+}
+
+/// Adds crate attributes to the ones in `attributes` that aren't there yet
+fn add_attributes(attributes: &mut String, new: &[&str]) {
+	for attribute in new {
+		// Declaring a feature twice is an error
+		if !attributes.lines().any(|line| line == *attribute) {
+			*attributes += attribute;
+			*attributes += "\n";
+		}
+	}
+}
+
+impl Session {
+	fn is_empty(&self) -> bool {
+		self.attributes.is_empty() && self.items.is_empty()
+	}
+
+	/// The remembered definitions, as one snippet
+	fn definitions(&self) -> String {
+		format!("{}\n{}", self.attributes, self.items)
+			.trim()
+			.to_owned()
+	}
+
+	/// The remembered items that the snippet doesn't define again, separated by empty lines
+	fn items_without(&self, snippet: &Snippet<'_>) -> String {
+		parse_snippet(&self.items)
+			.items
+			.into_iter()
+			.filter(|(name, _)| {
+				name.is_none() || !snippet.items.iter().any(|(other, _)| other == name)
+			})
+			.map(|(_, source)| source)
+			.collect::<Vec<_>>()
+			.join("\n\n")
+	}
+
+	/// The remembered definitions followed by the snippet. Crate attributes come first, so that
+	/// they are hoisted out of the generated main function. Items that the snippet defines again
+	/// are left out, so that they don't conflict
+	fn program(&self, snippet: &Snippet<'_>) -> String {
+		let mut attributes = self.attributes.clone();
+		add_attributes(&mut attributes, &snippet.attributes);
+		format!(
+			"{}\n{}\n{}",
+			attributes,
+			self.items_without(snippet),
+			snippet.body
+		)
+	}
+
+	/// Remembers the definitions of the snippet. Items replace remembered items with the same name,
+	/// so that they can be corrected
+	fn remember(&mut self, snippet: &Snippet<'_>) {
+		add_attributes(&mut self.attributes, &snippet.attributes);
+
+		let mut items = self.items_without(snippet);
+		for (_, source) in &snippet.items {
+			if !items.is_empty() {
+				items += "\n\n";
+			}
+			items += source;
+		}
+		self.items = items;
+	}
+}
+
+async fn load_session(ctx: Context<'_>) -> Result<Session, Error> {
+	let row = sqlx::query_as::<_, (String, String)>(
+		"SELECT attributes, items FROM repl_sessions WHERE channel_id = $1 AND updated_at > $2",
+	)
+	.bind(ctx.channel_id().get() as i64)
+	.bind(unix_now() - SESSION_TIMEOUT)
+	.fetch_optional(&ctx.data().database)
+	.await?;
+
+	Ok(row
+		.map(|(attributes, items)| Session { attributes, items })
+		.unwrap_or_default())
+}
+
+/// Stores the session of the channel and forgets the sessions that expired
+async fn save_session(ctx: Context<'_>, session: &Session) -> Result<(), Error> {
+	let database = &ctx.data().database;
+	let now = unix_now();
+
+	sqlx::query(
+		"INSERT INTO repl_sessions (channel_id, attributes, items, updated_at)
+		VALUES ($1, $2, $3, $4)
+		ON CONFLICT (channel_id) DO UPDATE SET attributes = EXCLUDED.attributes,
+			items = EXCLUDED.items, updated_at = EXCLUDED.updated_at",
+	)
+	.bind(ctx.channel_id().get() as i64)
+	.bind(&session.attributes)
+	.bind(&session.items)
+	.bind(now)
+	.execute(database)
+	.await?;
+	sqlx::query("DELETE FROM repl_sessions WHERE updated_at <= $1")
+		.bind(now - SESSION_TIMEOUT)
+		.execute(database)
+		.await?;
+	Ok(())
+}
+
+/// Runs a ?repl program, i.e. the remembered definitions followed by the new code. Returns the
+/// code as it was sent to the playground, along with the result
+pub async fn run_repl(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<(String, PlayResult), Error> {
+	let full_code = maybe_wrapped(code, ResultHandling::Repl, false, false, flags.runtime);
+
+	let mut result = run_on_playground(
+		ctx,
+		"repl",
+		code,
+		&full_code,
+		flags,
+		execute_with_live_output(
+			ctx,
+			&PlaygroundRequest {
+				code: &full_code,
+				channel: flags.channel,
+				crate_type: CrateType::Binary,
+				edition: flags.edition,
+				mode: flags.mode,
+				tests: false,
+				backtrace: flags.backtrace,
+			},
+		),
+	)
+	.await?;
+
+	result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
+
+	Ok((full_code.into_owned(), result))
+}
+
+/// Run Rust code in a REPL session that remembers definitions
+#[poise::command(
+	prefix_command,
+	track_edits,
+	subcommands("repl_reset", "repl_show"),
+	help_text_fn = "repl_help",
+	category = "Playground"
+)]
+pub async fn repl(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
+
+	let mut session = load_session(ctx).await?;
+	let snippet = parse_snippet(&code);
+	let program = session.program(&snippet);
+
+	let (full_code, result) = run_repl(ctx, &flags, &program).await?;
+
+	// Definitions that don't compile would break every following invocation
+	if result.success {
+		session.remember(&snippet);
+	}
+	save_session(ctx, &session).await?;
+
+	let program: &str = &program;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_repl(ctx, &flags, program).await?.1) }),
+	)
+	.await
+}
+
+/// Forget the definitions of the REPL session in this channel
+#[poise::command(prefix_command, rename = "reset", track_edits)]
+pub async fn repl_reset(ctx: Context<'_>) -> Result<(), Error> {
+	sqlx::query("DELETE FROM repl_sessions WHERE channel_id = $1")
+		.bind(ctx.channel_id().get() as i64)
+		.execute(&ctx.data().database)
+		.await?;
+
+	ctx.say("Reset the REPL session of this channel").await?;
+	Ok(())
+}
+
+/// Show the definitions that the REPL session in this channel remembers
+#[poise::command(prefix_command, rename = "show", track_edits)]
+pub async fn repl_show(ctx: Context<'_>) -> Result<(), Error> {
+	let session = load_session(ctx).await?;
+	if session.is_empty() {
+		ctx.say("The REPL session of this channel doesn't have any definitions yet")
+			.await?;
+		return Ok(());
+	}
+
+	let definitions = session.definitions();
+	let output = LongOutput {
+		header: "Definitions of the REPL session in this channel:\n",
+		blocks: vec![("rust", definitions.as_str())],
+		footer: "",
+	};
+	let mut reply = send_long_output(
+		ctx,
+		&output,
+		OutputMode::Auto,
+		async { None },
+		vec![],
+		vec![],
+	)
+	.await?;
+	// Without extra buttons, this only handles the page buttons until they time out
+	reply.next_interaction(ctx).await?;
+	Ok(())
+}
+
+#[must_use]
+pub fn repl_help() -> String {
+	generic_help(GenericHelp {
+		command: "repl",
+		desc: "\
+Run Rust code in a REPL session and print every top-level expression. Items like structs, \
+functions, impls and uses are remembered for the following invocations in the same channel or \
+thread, until the session wasn't used for an hour. Defining an item again replaces it. \
+`?repl show` shows the remembered definitions and `?repl reset` forgets them.",
+		flags: &["mode", "channel", "warn", "runtime", "backtrace"],
+		example_code: "code",
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_snippet_splits_attributes_items_and_body() {
+		let code = "#![feature(never_type)]\nstruct Foo;\nimpl Foo {}\nlet x = 1;\nx";
+		let snippet = parse_snippet(code);
+		assert_eq!(snippet.attributes, ["#![feature(never_type)]"]);
+		assert_eq!(
+			snippet.items,
+			[
+				(Some("Foo".to_owned()), "struct Foo;"),
+				(None, "impl Foo {}")
+			]
+		);
+		assert_eq!(snippet.body, "\nstruct Foo;\nimpl Foo {}\nlet x = 1;\nx");
+	}
+
+	#[test]
+	fn parse_snippet_skips_main() {
+		let snippet = parse_snippet("fn main() {}\nfn helper() {}");
+		assert_eq!(
+			snippet.items,
+			[(Some("helper".to_owned()), "fn helper() {}")]
+		);
+	}
+
+	#[test]
+	fn parse_snippet_remembers_nothing_of_invalid_code() {
+		let code = "struct Foo;\nlet x = ;";
+		let snippet = parse_snippet(code);
+		assert!(snippet.attributes.is_empty());
+		assert!(snippet.items.is_empty());
+		assert_eq!(snippet.body, code);
+	}
+
+	#[test]
+	fn item_name_ignores_underscore() {
+		let item = |code| syn::parse_str::<syn::Item>(code).unwrap();
+		assert_eq!(item_name(&item("const _: () = ();")), None);
+		assert_eq!(item_name(&item("use std::fmt;")), None);
+		assert_eq!(item_name(&item("enum E {}")), Some("E".to_owned()));
+		assert_eq!(
+			item_name(&item("macro_rules! m { () => {} }")),
+			Some("m".to_owned())
+		);
+	}
+
+	#[test]
+	fn add_attributes_skips_duplicates() {
+		let mut attributes = "#![feature(a)]\n".to_owned();
+		add_attributes(&mut attributes, &["#![feature(a)]", "#![feature(b)]"]);
+		assert_eq!(attributes, "#![feature(a)]\n#![feature(b)]\n");
+	}
+
+	#[test]
+	fn redefined_items_replace_remembered_ones() {
+		let mut session = Session::default();
+		session.remember(&parse_snippet("struct Foo;\nfn f() {}"));
+		assert_eq!(session.items, "struct Foo;\n\nfn f() {}");
+
+		let snippet = parse_snippet("struct Foo(u8);\nFoo(1).0");
+		let program = session.program(&snippet);
+		assert!(!program.contains("struct Foo;"));
+		assert!(program.contains("fn f() {}"));
+		assert!(program.contains("struct Foo(u8);"));
+
+		session.remember(&snippet);
+		assert_eq!(session.items, "fn f() {}\n\nstruct Foo(u8);");
+	}
+
+	#[test]
+	fn program_puts_attributes_first() {
+		let mut session = Session::default();
+		session.remember(&parse_snippet("#![feature(a)]\nfn f() {}"));
+		let program = session.program(&parse_snippet("#![feature(b)]\nf()"));
+		assert!(program.starts_with("#![feature(a)]\n#![feature(b)]\n"));
+		assert_eq!(session.definitions(), "#![feature(a)]\n\nfn f() {}");
+	}
+}
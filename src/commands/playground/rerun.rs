@@ -0,0 +1,93 @@
+use anyhow::{bail, Error};
+
+use crate::types::Context;
+
+use super::{
+	api::{CommandFlags, CompileTarget, PlayResult},
+	compile::run_compile,
+	microbench::run_microbench,
+	misc_commands::{run_clippy, run_expand, run_fmt, run_miri},
+	play_eval::run_play_or_eval,
+	procmacro::{run_procmacro, split_procmacro_code},
+	repl::run_repl,
+	test::run_test,
+	util::{parse_flags_with_code, send_reply, stub_message, ResultHandling},
+};
+
+/// Runs `code` like the playground command called `command` would, e.g. to repeat an entry of the
+/// command history. Returns the code as it was sent to the playground, along with the result
+async fn run_playground_command(
+	ctx: Context<'_>,
+	command: &str,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<Option<(String, PlayResult)>, Error> {
+	let compile_target = |target| async move {
+		let result = run_compile(ctx, flags, code, target).await?;
+		Ok::<_, Error>(Some((code.to_owned(), result)))
+	};
+
+	match command {
+		"play" => Ok(Some(
+			run_play_or_eval(ctx, flags, code, ResultHandling::None).await?,
+		)),
+		"eval" => Ok(Some(
+			run_play_or_eval(ctx, flags, code, ResultHandling::Print).await?,
+		)),
+		"repl" => Ok(Some(run_repl(ctx, flags, code).await?)),
+		"miri" => Ok(Some(run_miri(ctx, flags, code).await?)),
+		"expand" => Ok(Some(run_expand(ctx, flags, code).await?)),
+		"clippy" => Ok(Some(run_clippy(ctx, flags, code).await?)),
+		"fmt" => Ok(Some(run_fmt(ctx, flags, code).await?)),
+		"microbench" => run_microbench(ctx, flags, code).await,
+		"procmacro" => {
+			let Some((macro_code, usage_code)) = split_procmacro_code(code) else {
+				bail!("The stored proc macro code is malformed");
+			};
+			Ok(Some(
+				run_procmacro(ctx, flags, macro_code, usage_code).await?,
+			))
+		}
+		"test" => Ok(Some((code.to_owned(), run_test(ctx, flags, code).await?))),
+		"mir" => compile_target(CompileTarget::Mir).await,
+		"hir" => compile_target(CompileTarget::Hir).await,
+		"ir" => compile_target(CompileTarget::LlvmIr).await,
+		"asm" => compile_target(CompileTarget::Asm).await,
+		"wasm" => compile_target(CompileTarget::Wasm).await,
+		_ => bail!("`{}` can't be run again", command),
+	}
+}
+
+/// Runs `code` again with the playground command called `command` and replies with the result, as
+/// if the command was invoked with `flags`
+pub async fn rerun_playground(
+	ctx: Context<'_>,
+	command: &str,
+	flags: poise::KeyValueArgs,
+	code: &str,
+) -> Result<(), Error> {
+	ctx.say(stub_message(ctx)).await?;
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, code);
+
+	let Some((full_code, result)) = run_playground_command(ctx, command, &flags, code).await?
+	else {
+		return Ok(());
+	};
+
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| {
+			Box::pin(async move {
+				match run_playground_command(ctx, command, &flags, code).await? {
+					Some((_, result)) => Ok(result),
+					None => bail!("There's nothing to run"),
+				}
+			})
+		},
+	)
+	.await
+}
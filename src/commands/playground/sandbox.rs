@@ -0,0 +1,425 @@
+//! Run code on the bot host itself, in a resource-limited container without network access
+//!
+//! This mirrors what play.rust-lang.org does: the code is put into a cargo project called
+//! `playground` under `/playground`, and cargo is invoked on it. That way the compiler output looks
+//! the same as the playground's, and the output post-processing of the commands keeps working.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Error};
+use rand::Rng as _;
+use shuttle_runtime::SecretStore;
+use tracing::{info, warn};
+
+use super::api::{
+	Architecture, AssemblyFlavour, BoxFuture, ClippyRequest, CommandFlags, CompileRequest,
+	CompileResponse, CompileTarget, CrateType, Edition, FormatRequest, FormatResponse,
+	HttpPlayground, MacroExpansionRequest, MiriRequest, Mode, PlayResult, PlaygroundBackend,
+	PlaygroundRequest,
+};
+
+#[derive(Debug, Clone)]
+pub struct SandboxConfig {
+	/// Container runtime executable, `docker` or `podman`
+	pub runtime: String,
+	/// Container image with rustup and the stable, beta and nightly toolchains installed,
+	/// including the miri, clippy and rustfmt components
+	pub image: String,
+	/// How long the cargo invocation may take before it's killed
+	pub timeout: Duration,
+	/// Memory limit of the container, in the runtime's `--memory` syntax (e.g. `512m`)
+	pub memory: String,
+	/// CPU limit of the container, in the runtime's `--cpus` syntax (e.g. `1.5`)
+	pub cpus: String,
+}
+
+impl SandboxConfig {
+	pub fn from_secrets(secret_store: &SecretStore) -> Result<Self, Error> {
+		Ok(Self {
+			runtime: secret_store
+				.get("SANDBOX_RUNTIME")
+				.unwrap_or_else(|| "docker".to_owned()),
+			image: secret_store.get("SANDBOX_IMAGE").ok_or(anyhow!(
+				"Failed to get 'SANDBOX_IMAGE' from the secret store"
+			))?,
+			timeout: Duration::from_secs(
+				secret_store
+					.get("SANDBOX_TIMEOUT")
+					.map(|s| s.parse::<u64>())
+					.transpose()?
+					.unwrap_or(15),
+			),
+			memory: secret_store
+				.get("SANDBOX_MEMORY")
+				.unwrap_or_else(|| "512m".to_owned()),
+			cpus: secret_store
+				.get("SANDBOX_CPUS")
+				.unwrap_or_else(|| "1".to_owned()),
+		})
+	}
+}
+
+/// The cargo project that is compiled inside the sandbox
+struct Project<'a> {
+	code: &'a str,
+	edition: Edition,
+	crate_type: CrateType,
+}
+
+impl Project<'_> {
+	async fn write_to(&self, dir: &Path) -> Result<(), Error> {
+		tokio::fs::create_dir_all(dir.join("src")).await?;
+		tokio::fs::write(
+			dir.join("Cargo.toml"),
+			format!(
+				"[package]\nname = \"playground\"\nversion = \"0.0.1\"\nedition = \"{}\"\n",
+				self.edition.as_str()
+			),
+		)
+		.await?;
+		let source_file = match self.crate_type {
+			CrateType::Binary => "src/main.rs",
+			CrateType::Library => "src/lib.rs",
+		};
+		tokio::fs::write(dir.join(source_file), self.code).await?;
+		Ok(())
+	}
+
+	/// The project has no dependencies and the sandbox no network access, so code that needs one
+	/// of the crates of the playground, like `runtime=tokio` does, can't be built here
+	fn check_crates(&self) -> Result<(), Error> {
+		if self.code.contains("#[tokio::main]") {
+			bail!("`runtime=tokio` needs the tokio crate, which the local sandbox doesn't have");
+		}
+		Ok(())
+	}
+}
+
+/// Runs code with a container runtime on the bot host, as an alternative to the public playground
+#[derive(Debug)]
+pub struct LocalSandbox {
+	config: SandboxConfig,
+	/// There's nothing to share from the local machine, so gists still go to this playground
+	sharing: HttpPlayground,
+}
+
+impl LocalSandbox {
+	pub fn new(config: SandboxConfig, sharing: HttpPlayground) -> Self {
+		Self { config, sharing }
+	}
+
+	/// Writes the project to a temporary directory and runs the shell command inside the
+	/// project directory in the sandbox
+	async fn run(&self, project: &Project<'_>, command: &str) -> Result<PlayResult, Error> {
+		// Only building needs the crates, rustfmt doesn't
+		if command.contains("cargo") {
+			project.check_crates()?;
+		}
+
+		let id = format!("rustbot-sandbox-{:016x}", rand::thread_rng().gen::<u64>());
+		let dir = std::env::temp_dir().join(&id);
+
+		let result = async {
+			project.write_to(&dir).await?;
+			self.run_in_container(&id, &dir, command).await
+		}
+		.await;
+
+		if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+			warn!(
+				"Failed to clean up sandbox directory {}: {}",
+				dir.display(),
+				e
+			);
+		}
+
+		result
+	}
+
+	/// Whether the kernel killed a process in the container because the container ran out of
+	/// memory
+	async fn oom_killed(&self, id: &str) -> bool {
+		let output = tokio::process::Command::new(&self.config.runtime)
+			.args(["inspect", "--format", "{{.State.OOMKilled}}", id])
+			.stdin(Stdio::null())
+			.output()
+			.await;
+		match output {
+			Ok(output) => String::from_utf8_lossy(&output.stdout).trim() == "true",
+			Err(e) => {
+				warn!("Failed to inspect sandbox {}: {}", id, e);
+				false
+			}
+		}
+	}
+
+	/// Stops the container if it's still running, and removes it
+	async fn remove_container(&self, id: &str) {
+		let status = tokio::process::Command::new(&self.config.runtime)
+			.args(["rm", "--force", id])
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.status()
+			.await;
+		if let Err(e) = status {
+			warn!("Failed to remove sandbox {}: {}", id, e);
+		}
+	}
+
+	async fn run_in_container(
+		&self,
+		id: &str,
+		dir: &Path,
+		command: &str,
+	) -> Result<PlayResult, Error> {
+		let timeout_secs = self.config.timeout.as_secs();
+
+		// The project is mounted read-only and copied over to a scratch filesystem, so nothing
+		// that happens inside the container can end up on the host
+		let script = format!(
+			"cp -r /input/. /playground && cd /playground && timeout --signal=KILL {timeout_secs} {command}"
+		);
+		let mut volume = dir.as_os_str().to_owned();
+		volume.push(":/input:ro");
+
+		info!("Running `{}` in sandbox {}", command, id);
+		// Without `--rm`, so that the state of the container can be inspected once it stopped
+		let child = tokio::process::Command::new(&self.config.runtime)
+			.args(["run", "--name", id])
+			.args(["--network", "none"])
+			.args(["--memory", self.config.memory.as_str()])
+			.args(["--memory-swap", self.config.memory.as_str()])
+			.args(["--cpus", self.config.cpus.as_str()])
+			.args(["--pids-limit", "512"])
+			.args(["--cap-drop", "ALL", "--security-opt", "no-new-privileges"])
+			.args([
+				"--read-only",
+				"--tmpfs",
+				"/playground:exec",
+				"--tmpfs",
+				"/tmp:exec",
+			])
+			.args(["--env", "HOME=/tmp"])
+			.arg("--volume")
+			.arg(volume)
+			.arg(&self.config.image)
+			.args(["sh", "-c", script.as_str()])
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.kill_on_drop(true)
+			.spawn()?;
+
+		// Starting and tearing down the container takes a while on top of the actual time limit
+		let grace_period = Duration::from_secs(30);
+		let Ok(output) =
+			tokio::time::timeout(self.config.timeout + grace_period, child.wait_with_output())
+				.await
+		else {
+			// Killing the runtime client process doesn't stop the container itself
+			self.remove_container(id).await;
+			bail!("sandbox didn't finish within {}s", timeout_secs);
+		};
+		let oom_killed = self.oom_killed(id).await;
+		self.remove_container(id).await;
+		let output = output?;
+
+		let mut stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+		// 128 + SIGKILL is the exit code of both `timeout` and the OOM killer, so the container
+		// state tells them apart. A timeout is phrased like the playground does, so the timeout
+		// detection in `send_reply` works for both
+		if oom_killed {
+			let _ = writeln!(
+				stderr,
+				"Killed: out of memory, the sandbox is limited to {}",
+				self.config.memory
+			);
+		} else if output.status.code() == Some(137) {
+			let _ = writeln!(stderr, "Killed: timeout --signal=KILL {timeout_secs}");
+		}
+
+		Ok(PlayResult {
+			success: output.status.success() && !oom_killed,
+			stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+			stderr,
+		})
+	}
+}
+
+fn release_flag(mode: Mode) -> &'static str {
+	match mode {
+		Mode::Debug => "",
+		Mode::Release => " --release",
+	}
+}
+
+impl PlaygroundBackend for LocalSandbox {
+	fn execute<'a>(
+		&'a self,
+		request: &'a PlaygroundRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			let subcommand = match (request.tests, request.crate_type) {
+				(true, _) => "test",
+				(false, CrateType::Binary) => "run",
+				(false, CrateType::Library) => "build",
+			};
+			let command = format!(
+				"{}cargo +{} {subcommand}{}",
+				if request.backtrace {
+					"env RUST_BACKTRACE=1 "
+				} else {
+					""
+				},
+				request.channel.as_str(),
+				release_flag(request.mode)
+			);
+
+			let project = Project {
+				code: request.code,
+				edition: request.edition,
+				crate_type: request.crate_type,
+			};
+			self.run(&project, &command).await
+		})
+	}
+
+	fn miri<'a>(
+		&'a self,
+		request: &'a MiriRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			let project = Project {
+				code: request.code,
+				edition: request.edition,
+				crate_type: CrateType::Binary,
+			};
+			let command = format!(
+				"env MIRIFLAGS='{}' cargo +nightly miri run",
+				request.miri_flags()
+			);
+			self.run(&project, &command).await
+		})
+	}
+
+	fn supports_miri_flags(&self) -> bool {
+		true
+	}
+
+	fn macro_expansion<'a>(
+		&'a self,
+		request: &'a MacroExpansionRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			let project = Project {
+				code: request.code,
+				edition: request.edition,
+				crate_type: CrateType::Binary,
+			};
+			self.run(&project, "cargo +nightly rustc -- -Zunpretty=expanded")
+				.await
+		})
+	}
+
+	fn clippy<'a>(
+		&'a self,
+		request: &'a ClippyRequest<'a>,
+	) -> BoxFuture<'a, Result<PlayResult, Error>> {
+		Box::pin(async move {
+			let project = Project {
+				code: request.code,
+				edition: request.edition,
+				crate_type: request.crate_type,
+			};
+			self.run(&project, "cargo +stable clippy").await
+		})
+	}
+
+	fn format<'a>(
+		&'a self,
+		request: &'a FormatRequest<'a>,
+	) -> BoxFuture<'a, Result<FormatResponse, Error>> {
+		Box::pin(async move {
+			let project = Project {
+				code: request.code,
+				edition: request.edition,
+				crate_type: CrateType::Binary,
+			};
+			let command = format!(
+				"rustfmt +stable --edition {} < src/main.rs",
+				request.edition.as_str()
+			);
+			let result = self.run(&project, &command).await?;
+
+			Ok(FormatResponse {
+				success: result.success,
+				code: result.stdout,
+				stderr: result.stderr,
+			})
+		})
+	}
+
+	fn compile<'a>(
+		&'a self,
+		request: &'a CompileRequest<'a>,
+	) -> BoxFuture<'a, Result<CompileResponse, Error>> {
+		Box::pin(async move {
+			// Demangling and filtering of assembly is done by the playground server, so the
+			// sandbox always gives out the raw assembly
+			let (cargo_args, rustc_args) = match (
+				request.target,
+				request.assembly_flavor,
+				request.architecture,
+			) {
+				(CompileTarget::Asm, AssemblyFlavour::Intel, Architecture::X86_64) => (
+					"",
+					"--emit=asm=/tmp/compilation -Cllvm-args=-x86-asm-syntax=intel",
+				),
+				// Intel syntax only exists on x86
+				(CompileTarget::Asm, _, _) => ("", "--emit=asm=/tmp/compilation"),
+				(CompileTarget::LlvmIr, _, _) => ("", "--emit=llvm-ir=/tmp/compilation"),
+				(CompileTarget::Mir, _, _) => ("", "--emit=mir=/tmp/compilation"),
+				(CompileTarget::Hir, _, _) => ("", "-Zunpretty=hir -o /tmp/compilation"),
+				(CompileTarget::Wasm, _, _) => (
+					" --target wasm32-unknown-unknown",
+					"--emit=asm=/tmp/compilation",
+				),
+			};
+			let cross_compile = match (request.target, request.architecture) {
+				(CompileTarget::Wasm, _) | (_, Architecture::X86_64) => String::new(),
+				(_, architecture) => format!(" --target {}", architecture.triple()),
+			};
+			// With multiple codegen units rustc can't write everything into a single file
+			let command = format!(
+				"cargo +{} rustc{}{cargo_args}{cross_compile} -- -Ccodegen-units=1 {rustc_args} && cat /tmp/compilation",
+				request.channel.as_str(),
+				release_flag(request.mode)
+			);
+
+			let project = Project {
+				code: request.code,
+				edition: request.edition,
+				crate_type: request.crate_type,
+			};
+			let result = self.run(&project, &command).await?;
+
+			Ok(CompileResponse {
+				success: result.success,
+				code: result.stdout,
+				stderr: result.stderr,
+			})
+		})
+	}
+
+	fn gist<'a>(&'a self, code: &'a str) -> BoxFuture<'a, Result<String, Error>> {
+		self.sharing.gist(code)
+	}
+
+	fn gist_url(&self, flags: &CommandFlags, gist_id: &str) -> String {
+		self.sharing.gist_url(flags, gist_id)
+	}
+}
@@ -0,0 +1,424 @@
+//! Slash command variants of the playground commands
+//!
+//! Slash command options can't hold multi-line code, so the code is entered in a modal. The
+//! flags are regular options instead of `key=value` pairs. Apart from that, the commands run
+//! through the same code as their prefix counterparts, which they're merged with in `main.rs`.
+
+use std::collections::HashMap;
+
+use anyhow::Error;
+
+use crate::helpers::code_from_modal;
+use crate::types::{ApplicationContext, Context};
+
+use super::{
+	api::CommandFlags,
+	flags::{find_flag, BOOL},
+	microbench::{rerun_microbench, run_microbench, BLACK_BOX_HINT},
+	misc_commands::{run_clippy, run_expand, run_fmt, run_miri},
+	play_eval::run_play_or_eval,
+	procmacro::run_procmacro,
+	util::{parse_flags_with_code, send_reply, stub_message, ResultHandling},
+};
+
+/// Suggests the values of the flag called `name` that match what was typed so far
+fn complete_flag(name: &str, partial: &str) -> impl Iterator<Item = String> {
+	let partial = partial.to_ascii_lowercase();
+	find_flag(name)
+		.map_or(&[][..], |flag| flag.values)
+		.iter()
+		.filter(move |value| value.contains(&partial))
+		.map(|&value| value.to_owned())
+}
+
+/// Completes the last of the comma-separated lints with a lint group
+#[allow(clippy::unused_async)] // poise requires autocomplete functions to be async
+async fn autocomplete_lints(_: Context<'_>, partial: &str) -> impl Iterator<Item = String> {
+	let (done, last) = partial.rsplit_once(',').unwrap_or(("", partial));
+	let prefix = if done.is_empty() {
+		String::new()
+	} else {
+		format!("{done},")
+	};
+	complete_flag("lints", last).map(move |group| format!("{prefix}{group}"))
+}
+
+/// Offers the values of [`super::flags::FLAGS`] as choices for the options named after a flag,
+/// so that the two can't drift apart. Those options are `usize`, because Discord sends the index
+/// of the chosen value, see [`flag_choice`]. Options with autocomplete and boolean options are left
+/// alone
+#[must_use]
+pub fn with_flag_choices(
+	mut command: poise::Command<crate::types::Data, Error>,
+) -> poise::Command<crate::types::Data, Error> {
+	for parameter in &mut command.parameters {
+		let Some(flag) = find_flag(&parameter.name) else {
+			continue;
+		};
+		if parameter.autocomplete_callback.is_some()
+			|| flag.values.is_empty()
+			|| flag.values == BOOL
+		{
+			continue;
+		}
+		parameter.choices = flag
+			.values
+			.iter()
+			.map(|&value| poise::CommandParameterChoice {
+				name: value.to_owned(),
+				localizations: HashMap::new(),
+				__non_exhaustive: (),
+			})
+			.collect();
+	}
+	command
+}
+
+/// The `key=value` flag of the choice at `index` of the option named after the flag `name`
+fn flag_choice(name: &'static str, index: Option<usize>) -> (&'static str, Option<String>) {
+	let value = index
+		.and_then(|index| find_flag(name)?.values.get(index))
+		.map(|&value| value.to_owned());
+	(name, value)
+}
+
+/// Parses the options of a slash command like the `key=value` flags of a prefix command, so
+/// that they're validated the same way, along with the flags in the code. Returns the flags and
+/// the parse errors
+fn flags_from_options<const N: usize>(
+	options: [(&str, Option<String>); N],
+	code: &str,
+) -> (CommandFlags, String) {
+	parse_flags_with_code(
+		poise::KeyValueArgs(
+			options
+				.into_iter()
+				.filter_map(|(name, value)| Some((name.to_owned(), value?)))
+				.collect::<HashMap<_, _>>(),
+		),
+		code,
+	)
+}
+
+/// In slash commands, replies can't replace the stub message like in prefix commands, so the
+/// stub is deleted once the reply is there
+async fn with_stub_message<T>(
+	ctx: Context<'_>,
+	future: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+	let stub = ctx.say(stub_message(ctx)).await?;
+	let result = future.await;
+	// Errors are ignored in case the stub message was deleted already
+	let _: Result<_, _> = stub.delete(ctx).await;
+	result
+}
+
+/// Compile and run Rust code in a playground
+#[poise::command(slash_command, rename = "play", category = "Playground")]
+#[allow(clippy::too_many_arguments)] // Every slash command option is an argument
+pub async fn play_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Release channel"] channel: Option<usize>,
+	#[description = "Compilation mode"] mode: Option<usize>,
+	#[description = "Rust edition"] edition: Option<usize>,
+	#[description = "Show compiler warnings"] warn: Option<bool>,
+	#[description = "Compile as a binary or a library"] crate_type: Option<usize>,
+	#[description = "Run the tests instead of main"] tests: Option<bool>,
+	#[description = "Set RUST_BACKTRACE=1"] backtrace: Option<bool>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options(
+		[
+			flag_choice("channel", channel),
+			flag_choice("mode", mode),
+			flag_choice("edition", edition),
+			("warn", warn.map(|warn| warn.to_string())),
+			flag_choice("crate_type", crate_type),
+			("tests", tests.map(|tests| tests.to_string())),
+			(
+				"backtrace",
+				backtrace.map(|backtrace| backtrace.to_string()),
+			),
+		],
+		&code,
+	);
+
+	let (full_code, result) = run_play_or_eval(ctx, &flags, &code, ResultHandling::None).await?;
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| {
+			Box::pin(async move {
+				Ok(run_play_or_eval(ctx, &flags, code, ResultHandling::None)
+					.await?
+					.1)
+			})
+		},
+	)
+	.await
+}
+
+/// Evaluate a single Rust expression
+#[poise::command(slash_command, rename = "eval", category = "Playground")]
+pub async fn eval_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Release channel"] channel: Option<usize>,
+	#[description = "Compilation mode"] mode: Option<usize>,
+	#[description = "Rust edition"] edition: Option<usize>,
+	#[description = "Show compiler warnings"] warn: Option<bool>,
+	#[description = "Set RUST_BACKTRACE=1"] backtrace: Option<bool>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options(
+		[
+			flag_choice("channel", channel),
+			flag_choice("mode", mode),
+			flag_choice("edition", edition),
+			("warn", warn.map(|warn| warn.to_string())),
+			(
+				"backtrace",
+				backtrace.map(|backtrace| backtrace.to_string()),
+			),
+		],
+		&code,
+	);
+
+	let (full_code, result) = run_play_or_eval(ctx, &flags, &code, ResultHandling::Print).await?;
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| {
+			Box::pin(async move {
+				Ok(run_play_or_eval(ctx, &flags, code, ResultHandling::Print)
+					.await?
+					.1)
+			})
+		},
+	)
+	.await
+}
+
+/// Run code and detect undefined behavior using Miri
+#[poise::command(slash_command, rename = "miri", category = "Playground")]
+pub async fn miri_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Rust edition"] edition: Option<usize>,
+	#[description = "Stacked Borrows or Tree Borrows"] aliasing: Option<usize>,
+	#[description = "Number of Miri seeds to try"] seeds: Option<u32>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options(
+		[
+			flag_choice("edition", edition),
+			flag_choice("aliasing", aliasing),
+			("seeds", seeds.map(|seeds| seeds.to_string())),
+		],
+		&code,
+	);
+
+	let (full_code, result) = with_stub_message(ctx, run_miri(ctx, &flags, &code)).await?;
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_miri(ctx, &flags, code).await?.1) }),
+	)
+	.await
+}
+
+/// Expand macros to their raw desugared form
+#[poise::command(slash_command, rename = "expand", category = "Playground")]
+pub async fn expand_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Rust edition"] edition: Option<usize>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options([flag_choice("edition", edition)], &code);
+
+	let (full_code, result) = with_stub_message(ctx, run_expand(ctx, &flags, &code)).await?;
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_expand(ctx, &flags, code).await?.1) }),
+	)
+	.await
+}
+
+/// Catch common mistakes using the Clippy linter
+#[poise::command(slash_command, rename = "clippy", category = "Playground")]
+pub async fn clippy_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Rust edition"] edition: Option<usize>,
+	#[description = "Lint groups or lints to enable, like pedantic,unwrap_used"]
+	#[autocomplete = "autocomplete_lints"]
+	lints: Option<String>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) =
+		flags_from_options([flag_choice("edition", edition), ("lints", lints)], &code);
+
+	let (full_code, result) = with_stub_message(ctx, run_clippy(ctx, &flags, &code)).await?;
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_clippy(ctx, &flags, code).await?.1) }),
+	)
+	.await
+}
+
+/// Format code using rustfmt
+#[poise::command(slash_command, rename = "fmt", category = "Playground")]
+pub async fn fmt_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Rust edition"] edition: Option<usize>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options([flag_choice("edition", edition)], &code);
+
+	let (full_code, result) = with_stub_message(ctx, run_fmt(ctx, &flags, &code)).await?;
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { Ok(run_fmt(ctx, &flags, code).await?.1) }),
+	)
+	.await
+}
+
+/// Benchmark small snippets of code
+#[poise::command(slash_command, rename = "microbench", category = "Playground")]
+pub async fn microbench_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Release channel"] channel: Option<usize>,
+	#[description = "Rust edition"] edition: Option<usize>,
+	#[description = "Show compiler warnings"] warn: Option<bool>,
+	#[description = "Run on both stable and nightly"] both_channels: Option<bool>,
+	#[description = "Render the results as a bar chart"] chart: Option<bool>,
+) -> Result<(), Error> {
+	let Some(code) = code_from_modal(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options(
+		[
+			flag_choice("channel", channel),
+			flag_choice("edition", edition),
+			("warn", warn.map(|warn| warn.to_string())),
+			(
+				"both_channels",
+				both_channels.map(|both_channels| both_channels.to_string()),
+			),
+			("chart", chart.map(|chart| chart.to_string())),
+		],
+		&code,
+	);
+
+	let Some((bench_code, result)) =
+		with_stub_message(ctx, run_microbench(ctx, &flags, &code)).await?
+	else {
+		return Ok(());
+	};
+
+	let mut header = flag_parse_errors;
+	if !code.contains("black_box") {
+		header += BLACK_BOX_HINT;
+	}
+	let code: &str = &code;
+	send_reply(ctx, result, &bench_code, &flags, &header, &move |flags| {
+		Box::pin(async move { rerun_microbench(ctx, &flags, code).await })
+	})
+	.await
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Procedural macro"]
+struct ProcMacroModal {
+	#[name = "Proc macro code"]
+	#[placeholder = "#[proc_macro]\npub fn foo(_: proc_macro::TokenStream) -> proc_macro::TokenStream { ... }"]
+	#[paragraph]
+	macro_code: String,
+	#[name = "Usage code (the macro crate is `procmacro`)"]
+	#[placeholder = "procmacro::foo!();"]
+	#[paragraph]
+	usage_code: String,
+}
+
+/// Compile and use a procedural macro
+#[poise::command(slash_command, rename = "procmacro", category = "Playground")]
+pub async fn procmacro_slash(
+	ctx: ApplicationContext<'_>,
+	#[description = "Show compiler warnings"] warn: Option<bool>,
+	#[description = "Run the usage code instead of only compiling it"] run: Option<bool>,
+	#[description = "Show the usage code after macro expansion"] expand: Option<bool>,
+) -> Result<(), Error> {
+	let Some(modal) = <ProcMacroModal as poise::Modal>::execute(ctx).await? else {
+		return Ok(());
+	};
+	let ctx = Context::Application(ctx);
+	let (flags, flag_parse_errors) = flags_from_options(
+		[
+			("warn", warn.map(|warn| warn.to_string())),
+			("run", run.map(|run| run.to_string())),
+			("expand", expand.map(|expand| expand.to_string())),
+		],
+		&modal.macro_code,
+	);
+
+	let (full_code, result) = with_stub_message(
+		ctx,
+		run_procmacro(ctx, &flags, &modal.macro_code, &modal.usage_code),
+	)
+	.await?;
+	let (macro_code, usage_code): (&str, &str) = (&modal.macro_code, &modal.usage_code);
+	send_reply(
+		ctx,
+		result,
+		&full_code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| {
+			Box::pin(async move { Ok(run_procmacro(ctx, &flags, macro_code, usage_code).await?.1) })
+		},
+	)
+	.await
+}
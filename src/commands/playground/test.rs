@@ -0,0 +1,319 @@
+use std::fmt::Write as _;
+
+use anyhow::Error;
+
+use crate::helpers::code_block_or_attachment;
+use crate::types::Context;
+
+use super::{
+	api::{CommandFlags, CrateType, PlayResult, PlaygroundRequest},
+	util::{
+		extract_relevant_lines, format_play_eval_stderr, generic_help, parse_flags_with_code,
+		run_on_playground, send_reply, stub_message, GenericHelp,
+	},
+};
+
+/// Test counts extracted from libtest's output
+#[derive(Default)]
+struct TestSummary<'a> {
+	passed: usize,
+	ignored: usize,
+	failed: Vec<&'a str>,
+}
+
+/// Returns `None` if the tests didn't get to run, e.g. because of a compilation error
+fn parse_libtest_output(stdout: &str) -> Option<TestSummary<'_>> {
+	if !stdout.contains("test result: ") {
+		return None;
+	}
+
+	let mut summary = TestSummary::default();
+	// Lines look like `test tests::foo ... ok`. There may be multiple test binaries (unit tests and
+	// doc tests), and we just sum them all up
+	for line in stdout.lines() {
+		let Some((name, outcome)) = line
+			.strip_prefix("test ")
+			.and_then(|line| line.rsplit_once(" ... "))
+		else {
+			continue;
+		};
+
+		// `#[should_panic]` tests are listed as `test foo - should panic ... ok`, but their output
+		// section is headed by just the name
+		let name = name.strip_suffix(" - should panic").unwrap_or(name);
+		match outcome {
+			"ok" => summary.passed += 1,
+			"FAILED" => summary.failed.push(name),
+			outcome if outcome.starts_with("ignored") => summary.ignored += 1,
+			_ => {}
+		}
+	}
+
+	Some(summary)
+}
+
+/// Extracts the panic message from the `---- test_name stdout ----` section that libtest prints
+/// for every failed test
+fn failure_message<'a>(stdout: &'a str, test_name: &str) -> Option<&'a str> {
+	let header = format!("---- {test_name} stdout ----\n");
+	let mut section = &stdout[(stdout.find(&header)? + header.len())..];
+
+	// The section ends with the next test's section, or the list of failed test names
+	if let Some(section_end) = ["\n---- ", "\nfailures:"]
+		.iter()
+		.filter_map(|t| section.find(t))
+		.min()
+	{
+		section = &section[..section_end];
+	}
+
+	// Skip `thread 'tests::foo' panicked at src/lib.rs:3:5:`, the message is on the lines after
+	if let Some(panic_pos) = section.find("panicked at") {
+		section = match section[panic_pos..].find('\n') {
+			Some(line_end) => &section[(panic_pos + line_end + 1)..],
+			None => "",
+		};
+	}
+
+	if let Some(note_pos) = section.find("note: run with `RUST_BACKTRACE") {
+		section = &section[..note_pos];
+	}
+
+	Some(section.trim())
+}
+
+fn format_test_summary(summary: &TestSummary<'_>, stdout: &str) -> String {
+	if summary.passed + summary.failed.len() + summary.ignored == 0 {
+		return "No tests found. Mark test functions with #[test]\n".to_owned();
+	}
+
+	let mut text = format!(
+		"test result: {} passed; {} failed; {} ignored\n",
+		summary.passed,
+		summary.failed.len(),
+		summary.ignored
+	);
+
+	for test_name in &summary.failed {
+		let _ = write!(text, "\n---- {test_name} ----\n");
+		if let Some(message) = failure_message(stdout, test_name) {
+			text += message;
+			text.push('\n');
+		}
+	}
+
+	text
+}
+
+/// Run the #[test] functions in Rust code
+#[poise::command(
+	prefix_command,
+	track_edits,
+	help_text_fn = "test_help",
+	category = "Playground"
+)]
+pub async fn test(
+	ctx: Context<'_>,
+	flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	let code = code_block_or_attachment(ctx, code, 0).await?;
+	ctx.say(stub_message(ctx)).await?;
+
+	let (flags, flag_parse_errors) = parse_flags_with_code(flags, &code);
+
+	let result = run_test(ctx, &flags, &code).await?;
+
+	let code: &str = &code;
+	send_reply(
+		ctx,
+		result,
+		code,
+		&flags,
+		&flag_parse_errors,
+		&move |flags| Box::pin(async move { run_test(ctx, &flags, code).await }),
+	)
+	.await
+}
+
+/// Runs the tests in the code like ?test does
+pub async fn run_test(
+	ctx: Context<'_>,
+	flags: &CommandFlags,
+	code: &str,
+) -> Result<PlayResult, Error> {
+	let mut result = run_on_playground(
+		ctx,
+		"test",
+		code,
+		code,
+		flags,
+		ctx.data().playground.execute(&PlaygroundRequest {
+			code,
+			channel: flags.channel,
+			// A library doesn't need a main function, which test snippets usually don't have
+			crate_type: CrateType::Library,
+			edition: flags.edition,
+			mode: flags.mode,
+			tests: true,
+			backtrace: flags.backtrace,
+		}),
+	)
+	.await?;
+
+	if let Some(summary) = parse_libtest_output(&result.stdout) {
+		let summary_text = format_test_summary(&summary, &result.stdout);
+		result.stdout = summary_text;
+
+		// The program stderr is just cargo listing the test binaries it runs, so only
+		// compiler warnings are of interest
+		result.stderr = if flags.warn {
+			extract_relevant_lines(
+				&result.stderr,
+				&["Compiling playground"],
+				&[
+					"warning emitted",
+					"warnings emitted",
+					"warning: `playground` (lib test) generated",
+					"Finished ",
+				],
+			)
+			.to_owned()
+		} else {
+			String::new()
+		};
+	} else {
+		result.stderr = format_play_eval_stderr(&result.stderr, flags.warn);
+	}
+
+	Ok(result)
+}
+
+#[must_use]
+pub fn test_help() -> String {
+	generic_help(GenericHelp {
+		command: "test",
+		desc: "Compile Rust code in test mode and run its #[test] functions. Shows how many tests \
+		passed, failed or were ignored, along with the panic messages of the failing tests",
+		flags: &["mode", "channel", "warn", "backtrace"],
+		example_code: "
+#[test]
+fn it_works() {
+    assert_eq!(2 + 2, 4);
+}
+",
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PASSED: &str = r"
+running 2 tests
+test adds ... ok
+test subtracts ... ok
+
+test result: ok. 2 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s
+";
+
+	const FAILED: &str = r"
+running 6 tests
+test adds ... ok
+test compares ... FAILED
+test panics ... FAILED
+test should_panic_and_does - should panic ... ok
+test should_panic_but_doesnt - should panic ... FAILED
+test slow ... ignored
+
+failures:
+
+---- compares stdout ----
+
+thread 'compares' (5987) panicked at src/lib.rs:8:5:
+assertion `left == right` failed: math is broken
+  left: 2
+ right: 3
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+---- panics stdout ----
+
+thread 'panics' (5988) panicked at src/lib.rs:13:5:
+oh no
+second line
+
+---- should_panic_but_doesnt stdout ----
+note: test did not panic as expected at src/lib.rs:24:4
+
+failures:
+    compares
+    panics
+    should_panic_but_doesnt
+
+test result: FAILED. 2 passed; 3 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.00s
+";
+
+	#[test]
+	fn all_tests_pass() {
+		let summary = parse_libtest_output(PASSED).unwrap();
+		assert_eq!(
+			format_test_summary(&summary, PASSED),
+			"test result: 2 passed; 0 failed; 0 ignored\n"
+		);
+	}
+
+	#[test]
+	fn failures_are_counted() {
+		let summary = parse_libtest_output(FAILED).unwrap();
+		assert_eq!(summary.passed, 2);
+		assert_eq!(summary.ignored, 1);
+		assert_eq!(
+			summary.failed,
+			["compares", "panics", "should_panic_but_doesnt"]
+		);
+	}
+
+	#[test]
+	fn panic_messages_are_extracted() {
+		assert_eq!(
+			failure_message(FAILED, "compares"),
+			Some("assertion `left == right` failed: math is broken\n  left: 2\n right: 3")
+		);
+		assert_eq!(
+			failure_message(FAILED, "panics"),
+			Some("oh no\nsecond line")
+		);
+		assert_eq!(failure_message(FAILED, "adds"), None);
+	}
+
+	#[test]
+	fn should_panic_failures_have_a_message() {
+		assert_eq!(
+			failure_message(FAILED, "should_panic_but_doesnt"),
+			Some("note: test did not panic as expected at src/lib.rs:24:4")
+		);
+		assert!(
+			format_test_summary(&parse_libtest_output(FAILED).unwrap(), FAILED).contains(
+				"\n---- should_panic_but_doesnt ----\nnote: test did not panic as expected"
+			)
+		);
+	}
+
+	#[test]
+	fn compile_failures_are_not_test_results() {
+		// The compiler errors are on stderr, the tests never ran
+		assert!(parse_libtest_output("").is_none());
+		assert!(parse_libtest_output("error[E0308]: mismatched types\n").is_none());
+	}
+
+	#[test]
+	fn no_tests_found() {
+		let stdout = "\nrunning 0 tests\n\ntest result: ok. 0 passed; 0 failed; 0 ignored; 0 \
+			measured; 0 filtered out; finished in 0.00s\n";
+		let summary = parse_libtest_output(stdout).unwrap();
+		assert_eq!(
+			format_test_summary(&summary, stdout),
+			"No tests found. Mark test functions with #[test]\n"
+		);
+	}
+}
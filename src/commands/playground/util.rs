@@ -1,12 +1,15 @@
 use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::ops::Range;
 
 use poise::serenity_prelude as serenity;
 use serenity::ComponentInteraction;
+use tracing::warn;
 
 use crate::types::Context;
 use crate::Error;
 
-use super::api;
+use super::{api, flags};
 
 // Small thing about multiline strings: while hacking on this file I was unsure how to handle
 // trailing newlines in multiline strings:
@@ -21,36 +24,155 @@ use super::api;
 
 /// Returns the parsed flags and a String of parse errors. The parse error string will have a
 /// trailing newline (except if empty)
-pub fn parse_flags(mut args: poise::KeyValueArgs) -> (api::CommandFlags, String) {
+#[must_use]
+pub fn parse_flags(args: poise::KeyValueArgs) -> (api::CommandFlags, String) {
 	let mut errors = String::new();
+	let mut flags = flags::DEFAULT_FLAGS;
 
-	let mut flags = api::CommandFlags {
-		channel: api::Channel::Nightly,
-		mode: api::Mode::Debug,
-		edition: api::Edition::E2024,
-		warn: false,
-		run: false,
-	};
+	// Sorted, so that the errors come in a stable order
+	let mut args = args.0.into_iter().collect::<Vec<_>>();
+	args.sort();
+	for (name, value) in args {
+		if let Some(flag) = flags::find_flag(&name) {
+			if let Err(e) = flag.set(&mut flags, &value) {
+				let _ = writeln!(errors, "{e}");
+			}
+		} else {
+			let _ = write!(errors, "unknown flag `{name}`");
+			let names = flags::FLAGS.iter().map(|flag| flag.name);
+			if let Some(suggestion) = flags::did_you_mean(&name, names) {
+				let _ = write!(errors, ". Did you mean `{suggestion}`?");
+			}
+			errors += "\n";
+		}
+	}
+
+	(flags, errors)
+}
+
+/// The `key=value` pairs of a flag comment like `// edition=2021 mode=release`, or `None` if the
+/// line isn't one. A comment only counts if it consists of nothing but `key=value` pairs
+fn flag_comment(line: &str) -> Option<Vec<(&str, &str)>> {
+	// Doc comments are prose, not flags
+	let comment = line
+		.trim()
+		.strip_prefix("//")
+		.filter(|comment| !comment.starts_with(['/', '!']))?;
+	comment
+		.split_whitespace()
+		.map(|pair| pair.split_once('='))
+		.collect::<Option<Vec<_>>>()
+		.filter(|pairs| !pairs.is_empty())
+}
 
-	macro_rules! pop_flag {
-		($flag_name:literal, $flag_field:expr) => {
-			if let Some(flag) = args.0.remove($flag_name) {
-				match flag.parse() {
-					Ok(x) => $flag_field = x,
-					Err(e) => errors += &format!("{}\n", e),
+/// Reads flags from the comments at the start of the code, see [`flag_comment`]
+fn code_flags(code: &str) -> poise::KeyValueArgs {
+	let mut flags = std::collections::HashMap::new();
+	for line in code.lines().map(str::trim) {
+		if line.is_empty() {
+			continue;
+		}
+		let Some(pairs) = flag_comment(line) else {
+			break;
+		};
+		for (key, value) in pairs {
+			flags.insert(key.to_owned(), value.to_owned());
+		}
+	}
+	poise::KeyValueArgs(flags)
+}
+
+/// Description of the first nightly-only syntax in the code, if any. The code is tokenized, so
+/// that comments and string literals don't count. `gen` is only a keyword since Rust 2024
+fn find_nightly_syntax(code: &str, edition: api::Edition) -> Option<&'static str> {
+	use proc_macro2::{Delimiter, TokenStream, TokenTree};
+
+	fn is_ident(token: Option<&TokenTree>, name: &str) -> bool {
+		matches!(token, Some(TokenTree::Ident(ident)) if ident == name)
+	}
+	fn is_punct(token: Option<&TokenTree>, c: char) -> bool {
+		matches!(token, Some(TokenTree::Punct(punct)) if punct.as_char() == c)
+	}
+	fn is_block(token: Option<&TokenTree>) -> bool {
+		matches!(token, Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace)
+	}
+
+	fn find(tokens: TokenStream, gen_is_keyword: bool) -> Option<&'static str> {
+		let tokens = tokens.into_iter().collect::<Vec<_>>();
+		(0..tokens.len()).find_map(|i| {
+			let token = tokens.get(i);
+			let next = tokens.get(i + 1);
+			match token? {
+				TokenTree::Punct(punct) if punct.as_char() == '#' && is_punct(next, '!') => {
+					let is_feature = matches!(
+						tokens.get(i + 2),
+						Some(TokenTree::Group(group))
+							if is_ident(group.stream().into_iter().next().as_ref(), "feature")
+					);
+					is_feature.then_some("`#![feature(...)]`")
+				}
+				TokenTree::Ident(ident) if ident == "try" && is_block(next) => Some("`try` blocks"),
+				TokenTree::Ident(ident) if ident == "do" && is_ident(next, "yeet") => {
+					Some("`do yeet`")
 				}
+				TokenTree::Ident(ident)
+					if ident == "gen"
+						&& gen_is_keyword && (is_block(next)
+						|| is_ident(next, "move") && is_block(tokens.get(i + 2))) =>
+				{
+					Some("`gen` blocks")
+				}
+				TokenTree::Group(group) => find(group.stream(), gen_is_keyword),
+				_ => None,
 			}
-		};
+		})
 	}
 
-	pop_flag!("channel", flags.channel);
-	pop_flag!("mode", flags.mode);
-	pop_flag!("edition", flags.edition);
-	pop_flag!("warn", flags.warn);
-	pop_flag!("run", flags.run);
+	let tokens = code.parse::<TokenStream>();
+	// See `maybe_wrapped`
+	proc_macro2::extra::invalidate_current_thread_spans();
+	find(tokens.ok()?, matches!(edition, api::Edition::E2024))
+}
+
+/// Like [`parse_flags`], but also reads flags from a comment at the start of the code, like
+/// `// edition=2021 mode=release`. Flags given to the command take precedence over the ones in
+/// the code. Warns if the code uses nightly-only syntax, but doesn't run on nightly
+pub fn parse_flags_with_code(
+	mut args: poise::KeyValueArgs,
+	code: &str,
+) -> (api::CommandFlags, String) {
+	let mut errors = String::new();
+
+	for (key, value) in code_flags(code).0 {
+		match args.0.get(&key) {
+			Some(arg) if *arg != value => {
+				let _ = writeln!(
+					errors,
+					"`{key}={arg}` overrides `{key}={value}` from the code"
+				);
+			}
+			Some(_) => {}
+			None => {
+				args.0.insert(key, value);
+			}
+		}
+	}
 
-	for (remaining_flag, _) in args.0 {
-		errors += &format!("unknown flag `{remaining_flag}`\n");
+	let (flags, flag_errors) = parse_flags(args);
+	errors += &flag_errors;
+
+	if let Some(syntax) = find_nightly_syntax(code, flags.edition) {
+		if !matches!(flags.channel, api::Channel::Nightly) {
+			let _ = writeln!(
+				errors,
+				"warning: {} only works on nightly, but the channel is {}",
+				syntax,
+				flags.channel.as_str()
+			);
+		}
+		if flags.both_channels {
+			let _ = writeln!(errors, "warning: {syntax} doesn't work on stable");
+		}
 	}
 
 	(flags, errors)
@@ -60,13 +182,17 @@ pub fn parse_flags(mut args: poise::KeyValueArgs) -> (api::CommandFlags, String)
 pub struct GenericHelp<'a> {
 	pub command: &'a str,
 	pub desc: &'a str,
-	pub mode_and_channel: bool,
-	pub warn: bool,
-	pub run: bool,
+	/// The flags the command takes besides [`flags::COMMON_FLAGS`]
+	pub flags: &'a [&'a str],
 	pub example_code: &'a str,
 }
 
 pub fn generic_help(spec: GenericHelp<'_>) -> String {
+	let flags = flags::FLAGS
+		.iter()
+		.filter(|flag| spec.flags.contains(&flag.name) || flags::COMMON_FLAGS.contains(&flag.name))
+		.collect::<Vec<_>>();
+
 	let mut reply = format!(
 		"{}. All code is executed on https://play.rust-lang.org.\n",
 		spec.desc
@@ -74,36 +200,86 @@ pub fn generic_help(spec: GenericHelp<'_>) -> String {
 
 	reply += "```rust\n?";
 	reply += spec.command;
-	if spec.mode_and_channel {
-		reply += " mode={} channel={}";
-	}
-	reply += " edition={}";
-	if spec.warn {
-		reply += " warn={}";
-	}
-	if spec.run {
-		reply += " run={}";
+	for flag in &flags {
+		let _ = write!(reply, " {}={{}}", flag.name);
 	}
 	reply += " ``\u{200B}`";
 	reply += spec.example_code;
 	reply += "``\u{200B}`\n```\n";
 
 	reply += "Optional arguments:\n";
-	if spec.mode_and_channel {
-		reply += "- mode: debug, release (default: debug)\n";
-		reply += "- channel: stable, beta, nightly (default: nightly)\n";
-	}
-	reply += "- edition: 2015, 2018, 2021, 2024 (default: 2024)\n";
-	if spec.warn {
-		reply += "- warn: true, false (default: false)\n";
-	}
-	if spec.run {
-		reply += "- run: true, false (default: false)\n";
+	for flag in &flags {
+		let _ = write!(
+			reply,
+			"- {}: {} (default: {})",
+			flag.name,
+			flag.values_str(),
+			flag.default()
+		);
+		if !flag.help.is_empty() {
+			let _ = write!(reply, ". {}", flag.help);
+		}
+		reply += "\n";
 	}
+	reply += "Flags can also be given in a comment at the start of the code, like \
+		`// edition=2021 mode=release`\n";
 
 	reply
 }
 
+/// Formats the flags that differ from the defaults as `key=value` pairs, so that they can be
+/// parsed again with [`parse_flags`]. Only flags that influence the result are included
+pub fn flags_to_args(flags: &api::CommandFlags) -> String {
+	flags::FLAGS
+		.iter()
+		.filter(|flag| flag.affects_result && flag.get(flags) != flag.default())
+		.map(|flag| format!("{}={}", flag.name, flag.get(flags)))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// Runs `run` on the playground, unless there's a cached result of `command` for the code and
+/// flags. The invocation is recorded in the user's history either way
+///
+/// `user_code` is the code as the user wrote it, `code` the code as it's sent to the playground.
+pub async fn run_on_playground(
+	ctx: Context<'_>,
+	command: &str,
+	user_code: &str,
+	code: &str,
+	flags: &api::CommandFlags,
+	run: impl std::future::Future<Output = Result<api::PlayResult, Error>>,
+) -> Result<api::PlayResult, Error> {
+	let key = flags.cache.then(|| {
+		let nightly = matches!(flags.channel, api::Channel::Nightly) || flags.both_channels;
+		crate::cache::CacheKey::new(command, code, &flags.cache_key(), nightly)
+	});
+	let result = Box::pin(crate::cache::cached(
+		ctx,
+		key,
+		crate::scheduler::Service::Playground,
+		run,
+	))
+	.await?;
+
+	let output = if result.stdout.trim().is_empty() {
+		&result.stderr
+	} else {
+		&result.stdout
+	};
+	crate::commands::history::record(
+		ctx,
+		command,
+		&flags_to_args(flags),
+		user_code,
+		result.success,
+		output,
+	)
+	.await;
+
+	Ok(result)
+}
+
 /// Strip the input according to a list of start tokens and end tokens. Everything after the start
 /// token up to the end token is stripped. Remaining trailing or loading empty lines are removed as
 /// well.
@@ -160,6 +336,8 @@ pub enum ResultHandling {
 	Discard,
 	/// Print the result with `println!("{:?}")`
 	Print,
+	/// Print every top-level expression along with its source, like a REPL session
+	Repl,
 }
 
 pub fn hoise_crate_attributes(code: &str, after_crate_attrs: &str, after_code: &str) -> String {
@@ -172,7 +350,9 @@ pub fn hoise_crate_attributes(code: &str, after_crate_attrs: &str, after_code: &
 	// need to be at the top of the file)
 	while let Some(line) = lines.peek() {
 		let line = line.trim();
-		if line.starts_with("#![") {
+		// Flag comments like `// edition=2021` are kept in front too, other comments belong to the
+		// code that follows them
+		if line.starts_with("#![") || flag_comment(line).is_some() {
 			output.push_str(line);
 			output.push('\n');
 		} else if line.is_empty() {
@@ -200,59 +380,198 @@ pub fn hoise_crate_attributes(code: &str, after_crate_attrs: &str, after_code: &
 /// To check, whether a wrap was done, check if the return type is `Cow::Borrowed` vs `Cow::Owned`
 /// If a wrap was done, also hoists crate attributes to the top so they keep working
 pub fn maybe_wrap(code: &str, result_handling: ResultHandling) -> Cow<'_, str> {
-	maybe_wrapped(code, result_handling, false, false)
+	maybe_wrapped(code, result_handling, false, false, api::Runtime::None)
+}
+
+/// Prints a value along with its source in REPL mode. Through autoref specialization, `()` isn't
+/// printed, and values without a `Debug` implementation are printed as their type name
+const REPL_HELPERS: &str = r#"
+struct __ReplValue<'a, T>(&'a T);
+trait __ReplUnit {
+    fn __repl_print(&self, _: &str) {}
+}
+impl __ReplUnit for &&__ReplValue<'_, ()> {}
+trait __ReplDebug {
+    fn __repl_print(&self, source: &str);
+}
+impl<T: std::fmt::Debug> __ReplDebug for &__ReplValue<'_, T> {
+    fn __repl_print(&self, source: &str) {
+        println!("{} = {:?}", source, self.0);
+    }
+}
+trait __ReplOther {
+    fn __repl_print(&self, source: &str);
+}
+impl<T> __ReplOther for __ReplValue<'_, T> {
+    fn __repl_print(&self, source: &str) {
+        println!("{} = <{}>", source, std::any::type_name::<T>());
+    }
 }
+"#;
 
+/// Rewrites each of the top-level expression statements into a print of the expression's value,
+/// labelled with its source. `expressions` are the byte ranges of the statements and the
+/// expressions in them, in order
+fn rewrite_for_repl(code: &str, expressions: &[(Range<usize>, Range<usize>)]) -> String {
+	let mut rewritten = String::new();
+	let mut position = 0;
+	for (statement, expression) in expressions {
+		let source = &code[expression.clone()];
+		let label = source.split_whitespace().collect::<Vec<_>>().join(" ");
+
+		rewritten += &code[position..statement.start];
+		// Matching on a reference keeps temporaries alive and doesn't move out of places
+		let _ = write!(
+			rewritten,
+			"match &({source}) {{ __repl_value => \
+			(&&&__ReplValue(__repl_value)).__repl_print({label:?}), }};"
+		);
+		position = statement.end;
+	}
+	rewritten += &code[position..];
+	rewritten
+}
+
+/// Like [`maybe_wrap`], with more options: `unsf` wraps the code in an `unsafe` block, `pretty`
+/// prints the result with `{:#?}`, and `runtime` selects the kind of `fn main`
 pub fn maybe_wrapped(
 	code: &str,
 	result_handling: ResultHandling,
 	unsf: bool,
 	pretty: bool,
+	runtime: api::Runtime,
 ) -> Cow<'_, str> {
 	#[allow(clippy::wildcard_imports)]
-	use syn::{parse::Parse, *};
+	use syn::{parse::Parse, spanned::Spanned, visit::Visit, *};
+
+	// We use syn to check whether there is a main function, and whether the code awaits outside
+	// of async functions and blocks.
+	struct Inline {
+		awaits: bool,
+		/// Byte ranges of the top-level expression statements, and of the expressions in them
+		expressions: Vec<(Range<usize>, Range<usize>)>,
+	}
+
+	#[derive(Default)]
+	struct AwaitFinder {
+		found: bool,
+	}
+
+	impl<'ast> Visit<'ast> for AwaitFinder {
+		fn visit_expr_await(&mut self, _: &'ast ExprAwait) {
+			self.found = true;
+		}
+
+		// Awaiting is fine in these, so don't look inside
+		fn visit_expr_async(&mut self, _: &'ast ExprAsync) {}
+		fn visit_expr_closure(&mut self, closure: &'ast ExprClosure) {
+			if closure.asyncness.is_none() {
+				visit::visit_expr_closure(self, closure);
+			}
+		}
+		fn visit_item(&mut self, _: &'ast Item) {}
 
-	// We use syn to check whether there is a main function.
-	struct Inline {}
+		// Macro arguments aren't parsed by syn, but most macros like `println!` take expressions
+		fn visit_macro(&mut self, mac: &'ast Macro) {
+			let args =
+				mac.parse_body_with(punctuated::Punctuated::<Expr, Token![,]>::parse_terminated);
+			for arg in args.iter().flatten() {
+				// The arguments don't live for 'ast, so they need a separate visitor
+				let mut await_finder = AwaitFinder::default();
+				await_finder.visit_expr(arg);
+				self.found |= await_finder.found;
+			}
+		}
+	}
 
 	impl Parse for Inline {
 		fn parse(input: parse::ParseStream<'_>) -> Result<Self> {
 			Attribute::parse_inner(input)?;
 			let stmts = Block::parse_within(input)?;
+			let mut await_finder = AwaitFinder::default();
+			let mut expressions = Vec::new();
 			for stmt in &stmts {
 				if let Stmt::Item(Item::Fn(ItemFn { sig, .. })) = stmt {
 					if sig.ident == "main" && sig.inputs.is_empty() {
 						return Err(input.error("main"));
 					}
 				}
+				await_finder.visit_stmt(stmt);
+
+				let (expression, semicolon) = match stmt {
+					Stmt::Expr(expr, semicolon) => (expr.span(), semicolon.as_ref()),
+					Stmt::Macro(StmtMacro {
+						mac, semi_token, ..
+					}) => (mac.span(), semi_token.as_ref()),
+					Stmt::Local(_) | Stmt::Item(_) => continue,
+				};
+				let expression = expression.byte_range();
+				let statement = match semicolon {
+					Some(semicolon) => expression.start..semicolon.span.byte_range().end,
+					None => expression.clone(),
+				};
+				expressions.push((statement, expression));
 			}
-			Ok(Self {})
+			Ok(Self {
+				awaits: await_finder.found,
+				expressions,
+			})
 		}
 	}
 
-	let Ok(Inline { .. }) = parse_str::<Inline>(code) else {
+	let inline = parse_str::<Inline>(code);
+	// With span locations, proc-macro2 keeps every parsed source around until the spans are
+	// invalidated. Only the byte ranges are needed from here on
+	proc_macro2::extra::invalidate_current_thread_spans();
+	let Ok(Inline {
+		awaits,
+		expressions,
+	}) = inline
+	else {
 		return Cow::Borrowed(code);
 	};
+	let tokio = match runtime {
+		api::Runtime::Auto => awaits,
+		api::Runtime::None => false,
+		api::Runtime::Tokio => true,
+	};
 
 	// These string subsitutions are not quite optimal, but they perfectly preserve formatting, which is very important.
 	// This function must not change the formatting of the supplied code or it will be confusing and hard to use.
 
 	// fn main boilerplate
 	let mut after_crate_attrs = match result_handling {
-		ResultHandling::None => "fn main() {\n",
+		ResultHandling::None | ResultHandling::Repl => "fn main() {\n",
 		ResultHandling::Discard => "fn main() { let _ = {\n",
 		ResultHandling::Print if pretty => "fn main() { println!(\"{:#?}\", {\n",
 		ResultHandling::Print => "fn main() { println!(\"{:?}\", {\n",
 	}
 	.to_owned();
 
+	let mut code = Cow::Borrowed(code);
+	if let ResultHandling::Repl = result_handling {
+		let helpers = if pretty {
+			REPL_HELPERS.replace("{:?}", "{:#?}")
+		} else {
+			REPL_HELPERS.to_owned()
+		};
+		after_crate_attrs += &helpers;
+		code = Cow::Owned(rewrite_for_repl(&code, &expressions));
+	}
+
+	if tokio {
+		// The awaited result is printed with ResultHandling::Print, because the code runs inside
+		// the async main
+		after_crate_attrs = format!("#[tokio::main]\nasync {after_crate_attrs}");
+	}
+
 	if unsf {
 		after_crate_attrs = format!("{after_crate_attrs}unsafe {{");
 	}
 
 	// fn main boilerplate counterpart
 	let mut after_code = match result_handling {
-		ResultHandling::None => "}",
+		ResultHandling::None | ResultHandling::Repl => "}",
 		ResultHandling::Discard => "}; }",
 		ResultHandling::Print => "}); }",
 	}
@@ -263,85 +582,213 @@ pub fn maybe_wrapped(
 	}
 
 	Cow::Owned(hoise_crate_attributes(
-		code,
+		&code,
 		&after_crate_attrs,
 		&after_code,
 	))
 }
 
-/// Send a Discord reply with the formatted contents of a Playground result
-pub async fn send_reply(
-	ctx: Context<'_>,
-	result: api::PlayResult,
-	code: &str,
-	flags: &api::CommandFlags,
-	flag_parse_errors: &str,
-) -> Result<(), Error> {
+const TIMEOUT_NOTE: &str = "Playground timeout detected";
+
+fn is_timeout(output: &str) -> bool {
+	output.contains("Killed") && output.contains("timeout") && output.contains("--signal=KILL")
+}
+
+/// Formats a Playground result like [`send_reply`] does, but as a standalone message, e.g. to
+/// reply to some other message than the command invocation
+pub fn result_message(result: &api::PlayResult, header: &str) -> serenity::CreateMessage {
 	let result = crate::helpers::merge_output_and_errors(&result.stdout, &result.stderr);
+	crate::helpers::long_output_message(&crate::helpers::LongOutput {
+		header,
+		blocks: vec![("rust", &*result)],
+		footer: if is_timeout(&result) {
+			TIMEOUT_NOTE
+		} else {
+			""
+		},
+	})
+}
 
-	// Discord displays empty code blocks weirdly if they're not formatted in a specific style,
-	// so we special-case empty code blocks
-	if result.trim().is_empty() {
-		ctx.say(format!("{flag_parse_errors}``` ```")).await?;
-		return Ok(());
-	}
+/// Runs the command again with different flags, for the buttons below a Playground reply.
+/// Returns the new result
+pub type Rerun<'a> = dyn Fn(api::CommandFlags) -> api::BoxFuture<'a, Result<api::PlayResult, Error>>
+	+ Send
+	+ Sync
+	+ 'a;
 
-	let timeout =
-		result.contains("Killed") && result.contains("timeout") && result.contains("--signal=KILL");
+fn result_buttons(
+	ctx: Context<'_>,
+	flags: &api::CommandFlags,
+	timeout: bool,
+) -> Vec<serenity::CreateActionRow> {
+	let button = |action: &str, label: &str| {
+		serenity::CreateButton::new(format!("{}-{action}", ctx.id()))
+			.label(label)
+			.style(serenity::ButtonStyle::Secondary)
+	};
 
-	let mut text_end = String::from("```");
+	let mut components = Vec::new();
 	if timeout {
-		text_end += "Playground timeout detected";
+		let retry = button("retry", "Retry").style(serenity::ButtonStyle::Primary);
+		components.push(serenity::CreateActionRow::Buttons(vec![retry]));
 	}
+	components.push(serenity::CreateActionRow::Buttons(vec![
+		button("release", "Release mode").disabled(matches!(flags.mode, api::Mode::Release)),
+		button("warn", "Warnings").disabled(flags.warn),
+		button("godbolt", "Godbolt"),
+		button("share", "Share"),
+		button("delete", "Delete").style(serenity::ButtonStyle::Danger),
+	]));
+	components
+}
 
-	let text = crate::helpers::trim_text(
-		&format!("{flag_parse_errors}```rust\n{result}"),
-		&text_end,
-		async {
-			format!(
-				"Output too large. Playground link: <{}>",
-				api::url_from_gist(flags, &api::post_gist(ctx, code).await.unwrap_or_default()),
-			)
-		},
-	)
-	.await;
+/// Answers a button press with a message that only the presser can see
+async fn ephemeral_response(
+	ctx: Context<'_>,
+	interaction: &ComponentInteraction,
+	content: impl Into<String>,
+) -> Result<(), Error> {
+	interaction
+		.create_response(
+			ctx,
+			serenity::CreateInteractionResponse::Message(
+				serenity::CreateInteractionResponseMessage::new()
+					.content(content)
+					.ephemeral(true),
+			),
+		)
+		.await?;
+	Ok(())
+}
 
-	let custom_id = ctx.id().to_string();
+/// Like [`ephemeral_response`], for responses that take a moment to produce
+async fn ephemeral_followup(
+	ctx: Context<'_>,
+	interaction: &ComponentInteraction,
+	content: impl std::future::Future<Output = Result<String, Error>>,
+) -> Result<(), Error> {
+	interaction.defer_ephemeral(ctx).await?;
+	let content = content.await.unwrap_or_else(|e| e.to_string());
+	interaction
+		.create_followup(
+			ctx,
+			serenity::CreateInteractionResponseFollowup::new()
+				.content(content)
+				.ephemeral(true),
+		)
+		.await?;
+	Ok(())
+}
 
-	let response = ctx
-		.send({
-			let mut b = poise::CreateReply::default().content(text);
-			if timeout {
-				b = b.components(vec![serenity::CreateActionRow::Buttons(vec![
-					serenity::CreateButton::new(&custom_id)
-						.label("Retry")
-						.style(serenity::ButtonStyle::Primary),
-				])]);
+/// Send a Discord reply with the formatted contents of a Playground result
+///
+/// Below the reply are buttons to re-run the command in release mode or with warnings, which
+/// calls `rerun` with the modified flags, as well as buttons to open the code in Godbolt or the
+/// Playground and to delete the reply. Once no button was pressed for a while, they're removed.
+pub async fn send_reply(
+	ctx: Context<'_>,
+	mut result: api::PlayResult,
+	code: &str,
+	flags: &api::CommandFlags,
+	flag_parse_errors: &str,
+	rerun: &Rerun<'_>,
+) -> Result<(), Error> {
+	let mut flags = flags.clone();
+	loop {
+		let output = crate::helpers::merge_output_and_errors(&result.stdout, &result.stderr);
+		let timeout = is_timeout(&output);
+
+		let output = if output.trim().is_empty() {
+			// Discord displays empty code blocks weirdly if they're not formatted in a specific
+			// style, so we special-case empty code blocks
+			crate::helpers::LongOutput {
+				header: flag_parse_errors,
+				blocks: vec![],
+				footer: "``` ```",
 			}
-			b
-		})
+		} else {
+			crate::helpers::LongOutput {
+				header: flag_parse_errors,
+				blocks: vec![("rust", &*output)],
+				footer: if timeout { TIMEOUT_NOTE } else { "" },
+			}
+		};
+		let attachments = if flags.chart {
+			super::microbench::render_chart(&result.stdout)
+				.map(|png| serenity::CreateAttachment::bytes(png, "microbench.png"))
+				.into_iter()
+				.collect()
+		} else {
+			vec![]
+		};
+		let truncation_msg = async {
+			match api::post_gist(ctx, code).await {
+				Ok(gist_id) => Some(format!(
+					"Output too large. Playground link: <{}>",
+					ctx.data().playground.gist_url(&flags, &gist_id),
+				)),
+				Err(e) => {
+					warn!("failed to create gist: {}", e);
+					None
+				}
+			}
+		};
+		let mut reply = crate::helpers::send_long_output(
+			ctx,
+			&output,
+			flags.output,
+			truncation_msg,
+			result_buttons(ctx, &flags, timeout),
+			attachments,
+		)
 		.await?;
 
-	if let Some(retry_pressed) = response
-		.message()
-		.await?
-		.await_component_interaction(ctx)
-		.filter(move |mci: &ComponentInteraction| mci.data.custom_id == custom_id)
-		.timeout(std::time::Duration::from_secs(600))
-		.await
-	{
-		retry_pressed.defer(&ctx).await?;
-		ctx.rerun().await?;
-	} else {
-		// If timed out, just remove the button
-		// Errors are ignored in case the reply was deleted
-		let _ = response
-			// TODO: Add code to remove button
-			.edit(ctx, poise::CreateReply::default())
-			.await;
-	}
+		let (interaction, new_flags) = loop {
+			let Some(interaction) = reply.next_interaction(ctx).await? else {
+				return Ok(());
+			};
 
-	Ok(())
+			let mut new_flags = flags.clone();
+			match interaction.data.custom_id.rsplit('-').next() {
+				Some("godbolt") => {
+					let link = async {
+						let url = crate::commands::godbolt::godbolt_link(ctx, code).await?;
+						Ok(format!("Godbolt link: <{url}>"))
+					};
+					ephemeral_followup(ctx, &interaction, link).await?;
+					continue;
+				}
+				Some("share") => {
+					let link = async {
+						let gist_id = api::post_gist(ctx, code).await?;
+						let url = ctx.data().playground.gist_url(&flags, &gist_id);
+						Ok(format!("Playground link: <{url}>"))
+					};
+					ephemeral_followup(ctx, &interaction, link).await?;
+					continue;
+				}
+				_ if interaction.user.id != ctx.author().id => {
+					ephemeral_response(ctx, &interaction, "Only the command invoker can do that")
+						.await?;
+					continue;
+				}
+				Some("delete") => {
+					interaction.defer(ctx).await?;
+					interaction.message.delete(ctx).await?;
+					return Ok(());
+				}
+				Some("release") => new_flags.mode = api::Mode::Release,
+				Some("warn") => new_flags.warn = true,
+				_ => {}
+			}
+			break (interaction, new_flags);
+		};
+
+		interaction.defer(ctx).await?;
+		reply.remove_buttons(ctx).await;
+		result = rerun(new_flags.clone()).await?;
+		flags = new_flags;
+	}
 }
 
 // This function must not break when provided non-formatted text with messed up formatting: rustfmt
@@ -414,6 +861,105 @@ pub fn format_play_eval_stderr(stderr: &str, show_compiler_warnings: bool) -> St
 	}
 }
 
+/// Stub message content showing the latest output of a program that is still running
+fn live_output_message(output: &str) -> String {
+	// Leave some room for the surrounding text within Discord's 2000 character limit
+	const MAX_TAIL_LEN: usize = 1800;
+
+	let mut tail_start = output.len().saturating_sub(MAX_TAIL_LEN);
+	while !output.is_char_boundary(tail_start) {
+		tail_start += 1;
+	}
+
+	format!(
+		"_Running code on playground..._\n```\n{}\n```",
+		output[tail_start..].replace('`', "\u{200b}`")
+	)
+}
+
+/// Execute code, and show its output in the stub message while it's running
+///
+/// The stub message gets a Stop button that kills the program. It's removed again once the
+/// program finished; the caller is responsible for the final reply. In slash commands, the final
+/// reply can't replace the stub message, so the stub message is deleted instead.
+pub async fn execute_with_live_output(
+	ctx: Context<'_>,
+	request: &api::PlaygroundRequest<'_>,
+) -> Result<api::PlayResult, Error> {
+	// Discord allows about five message edits per five seconds
+	const EDIT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+	let stop_id = format!("{}-stop", ctx.id());
+	let response = ctx
+		.send(
+			poise::CreateReply::default()
+				.content(stub_message(ctx))
+				.components(vec![serenity::CreateActionRow::Buttons(vec![
+					serenity::CreateButton::new(&stop_id)
+						.label("Stop")
+						.style(serenity::ButtonStyle::Danger),
+				])]),
+		)
+		.await?;
+
+	let author_id = ctx.author().id;
+	let stop_pressed = response
+		.message()
+		.await?
+		.await_component_interaction(ctx)
+		.filter(move |mci: &ComponentInteraction| {
+			mci.data.custom_id == stop_id && mci.user.id == author_id
+		})
+		.timeout(std::time::Duration::from_mins(10));
+	let stop_pressed = async move { stop_pressed.await };
+	tokio::pin!(stop_pressed);
+
+	let (output_sender, mut output_receiver) = tokio::sync::mpsc::unbounded_channel();
+	let kill = std::sync::Arc::new(tokio::sync::Notify::new());
+	let mut execution =
+		ctx.data()
+			.playground
+			.execute_streaming(request, output_sender, kill.clone());
+
+	let mut output = String::new();
+	let mut output_changed = false;
+	let mut stop_handled = false;
+	let mut edit_interval = tokio::time::interval(EDIT_INTERVAL);
+	let result = loop {
+		tokio::select! {
+			result = &mut execution => break result,
+			Some(chunk) = output_receiver.recv() => {
+				output += &chunk;
+				output_changed = true;
+			}
+			stop = &mut stop_pressed, if !stop_handled => {
+				stop_handled = true;
+				if let Some(stop) = stop {
+					kill.notify_one();
+					stop.defer(&ctx).await?;
+				}
+			}
+			_ = edit_interval.tick(), if output_changed => {
+				output_changed = false;
+				// Errors are ignored in case the reply was deleted
+				let _: Result<_, _> = response
+					.edit(ctx, poise::CreateReply::default().content(live_output_message(&output)))
+					.await;
+			}
+		}
+	};
+
+	if let Context::Application(_) = ctx {
+		let _: Result<_, _> = response.delete(ctx).await;
+	} else {
+		let _: Result<_, _> = response
+			.edit(ctx, poise::CreateReply::default().components(vec![]))
+			.await;
+	}
+
+	result
+}
+
 pub fn stub_message(ctx: Context<'_>) -> String {
 	let mut stub_message = String::from("_Running code on playground..._\n");
 
@@ -430,3 +976,124 @@ pub fn stub_message(ctx: Context<'_>) -> String {
 	stub_message.truncate(2000);
 	stub_message
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The rewritten statement for an expression, as [`rewrite_for_repl`] generates it
+	fn print(source: &str, label: &str) -> String {
+		format!(
+			"match &({source}) {{ __repl_value => \
+			(&&&__ReplValue(__repl_value)).__repl_print({label:?}), }};"
+		)
+	}
+
+	#[test]
+	fn nightly_syntax_is_found() {
+		let find = |code| find_nightly_syntax(code, api::Edition::E2024);
+		assert_eq!(
+			find("#![feature(never_type)]\nfn main() {}"),
+			Some("`#![feature(...)]`")
+		);
+		assert_eq!(find("let x: Option<_> = try { 1 };"), Some("`try` blocks"));
+		assert_eq!(find("fn f() { do yeet 1; }"), Some("`do yeet`"));
+		assert_eq!(
+			find("let it = gen move { yield 1; };"),
+			Some("`gen` blocks")
+		);
+	}
+
+	#[test]
+	fn nightly_syntax_ignores_comments_strings_and_identifiers() {
+		let find = |code| find_nightly_syntax(code, api::Edition::E2024);
+		assert_eq!(find("// try { }\nlet s = \"try { }\";"), None);
+		assert_eq!(find("/// #![feature(x)]\nfn f() {}"), None);
+		assert_eq!(find("retry { }"), None);
+		assert_eq!(
+			find_nightly_syntax("match gen { _ => {} }", api::Edition::E2021),
+			None
+		);
+	}
+
+	#[test]
+	fn rewrite_for_repl_replaces_statements() {
+		let code = "let x = 1;\nx + 1;\nx";
+		let rewritten = rewrite_for_repl(code, &[(11..17, 11..16), (18..19, 18..19)]);
+		assert_eq!(
+			rewritten,
+			format!(
+				"let x = 1;\n{}\n{}",
+				print("x + 1", "x + 1"),
+				print("x", "x")
+			)
+		);
+	}
+
+	#[test]
+	fn rewrite_for_repl_collapses_whitespace_in_labels() {
+		let code = "vec![1,\n    2];";
+		let rewritten = rewrite_for_repl(code, &[(0..15, 0..14)]);
+		assert_eq!(rewritten, print("vec![1,\n    2]", "vec![1, 2]"));
+	}
+
+	#[test]
+	fn rewrite_for_repl_without_expressions() {
+		let code = "let x = 1;";
+		assert_eq!(rewrite_for_repl(code, &[]), code);
+	}
+
+	#[test]
+	fn repl_ranges_include_the_semicolon() {
+		let wrapped = maybe_wrapped(
+			"let x = 1;\nx + 1;\nprintln!(\"{x}\");\nx",
+			ResultHandling::Repl,
+			false,
+			false,
+			api::Runtime::None,
+		);
+		assert!(wrapped.contains(&format!("let x = 1;\n{}\n", print("x + 1", "x + 1"))));
+		assert!(wrapped.contains(&print("println!(\"{x}\")", "println!(\"{x}\")")));
+		assert!(wrapped.contains(&format!("{}\n}}", print("x", "x"))));
+		// A rewritten statement has exactly one semicolon at its end
+		assert!(!wrapped.contains("};;"));
+	}
+
+	#[test]
+	fn repl_leaves_items_alone() {
+		let wrapped = maybe_wrapped(
+			"fn f() -> i32 { 1 }\nf()",
+			ResultHandling::Repl,
+			false,
+			false,
+			api::Runtime::None,
+		);
+		assert!(wrapped.contains("fn f() -> i32 { 1 }\n"));
+		assert!(wrapped.contains(&print("f()", "f()")));
+	}
+
+	#[test]
+	fn only_flag_comments_are_hoisted() {
+		let wrapped = hoise_crate_attributes(
+			"// edition=2021\n#![allow(unused)]\n/// Docs\n// A comment\nstruct S;",
+			"fn main() {\n",
+			"}",
+		);
+		assert_eq!(
+			wrapped,
+			"// edition=2021\n#![allow(unused)]\nfn main() {\n/// Docs\n// A comment\nstruct S;\n}"
+		);
+	}
+
+	#[test]
+	fn flag_comments_need_only_pairs() {
+		assert_eq!(
+			flag_comment("// edition=2021 mode=release"),
+			Some(vec![("edition", "2021"), ("mode", "release")])
+		);
+		assert_eq!(flag_comment("// set mode=release"), None);
+		assert_eq!(flag_comment("/// mode=release"), None);
+		assert_eq!(flag_comment("//! mode=release"), None);
+		assert_eq!(flag_comment("let x = 1; // x=1"), None);
+	}
+}
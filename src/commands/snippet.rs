@@ -0,0 +1,370 @@
+//! A library of named code snippets, for examples that are needed again and again
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Error};
+
+use crate::cache::unix_now;
+use crate::commands::history::{flags_to_string, merge_flags};
+use crate::helpers::{code_block_or_attachment, send_long_output, LongOutput, OutputMode};
+use crate::types::Context;
+
+/// Owner of the snippets in the shared namespace, which is curated by the moderators
+const SHARED_OWNER: i64 = 0;
+/// How many snippets ?snippet list shows
+const LIST_LENGTH: i64 = 50;
+const MAX_NAME_LENGTH: usize = 32;
+
+/// The playground commands that snippets can be run with
+const SNIPPET_COMMANDS: [&str; 13] = [
+	"play",
+	"eval",
+	"miri",
+	"expand",
+	"clippy",
+	"fmt",
+	"microbench",
+	"test",
+	"mir",
+	"hir",
+	"ir",
+	"asm",
+	"wasm",
+];
+
+struct Snippet {
+	owner_id: i64,
+	name: String,
+	command: String,
+	flags: String,
+	code: String,
+	public: bool,
+}
+
+impl Snippet {
+	/// Short description of who the snippet belongs to
+	fn owner(&self) -> String {
+		if self.owner_id == SHARED_OWNER {
+			"shared".to_owned()
+		} else if self.public {
+			format!("public, by <@{}>", self.owner_id)
+		} else {
+			format!("by <@{}>", self.owner_id)
+		}
+	}
+}
+
+fn check_name(name: &str) -> Result<String, Error> {
+	let name = name.to_lowercase();
+	if name.is_empty()
+		|| name.len() > MAX_NAME_LENGTH
+		|| !name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+	{
+		bail!(
+			"Snippet names must be 1 to {} characters long and may only contain letters, digits, `-` \
+			and `_`",
+			MAX_NAME_LENGTH
+		);
+	}
+	Ok(name)
+}
+
+/// Removes a boolean option that is meant for the snippet itself, not for the playground
+fn pop_bool(flags: &mut poise::KeyValueArgs, key: &str) -> Result<bool, Error> {
+	Ok(flags
+		.0
+		.remove(key)
+		.map(|value| value.parse::<bool>())
+		.transpose()?
+		.unwrap_or(false))
+}
+
+/// Looks up a snippet by name. The invoker's own snippets come first, then the shared ones, then
+/// the public snippets of other users
+async fn find_snippet(ctx: Context<'_>, name: &str) -> Result<Snippet, Error> {
+	let name = check_name(name)?;
+	let row = sqlx::query_as::<_, (i64, String, String, String, String, bool)>(
+		"SELECT owner_id, name, command, flags, code, public FROM snippets
+		WHERE name = $1 AND (owner_id = $2 OR owner_id = $3 OR public)
+		ORDER BY owner_id = $2 DESC, owner_id = $3 DESC, updated_at DESC
+		LIMIT 1",
+	)
+	.bind(&name)
+	.bind(ctx.author().id.get() as i64)
+	.bind(SHARED_OWNER)
+	.fetch_optional(&ctx.data().database)
+	.await?;
+
+	let Some((owner_id, name, command, flags, code, public)) = row else {
+		bail!("There's no snippet called `{}`, see ?snippet list", name);
+	};
+	Ok(Snippet {
+		owner_id,
+		name,
+		command,
+		flags,
+		code,
+		public,
+	})
+}
+
+/// Save, run and share code snippets
+///
+/// Snippets are looked up by name in your own snippets first, then in the shared snippets curated \
+/// by the moderators, then in the public snippets of other users.
+/// ```
+/// ?snippet save <name> $($flags )* ``​`code``​`
+/// ?snippet run <name> $($flags )*
+/// ?snippet show <name>
+/// ?snippet list [mine|shared|public]
+/// ?snippet delete <name>
+/// ```
+#[poise::command(
+	prefix_command,
+	category = "Playground",
+	subcommands(
+		"snippet_save",
+		"snippet_run",
+		"snippet_show",
+		"snippet_list",
+		"snippet_delete"
+	),
+	subcommand_required
+)]
+#[allow(clippy::unused_async)] // poise requires command functions to be async
+pub async fn snippet(_: Context<'_>) -> Result<(), Error> {
+	Ok(())
+}
+
+/// Save a code snippet under a name
+///
+/// Saves the code along with the command and the flags it's run with by default. Saving under an \
+/// existing name replaces the snippet.
+/// ```
+/// ?snippet save <name> command={} public={} shared={} $($flags )* ``​`
+/// code
+/// ``​`
+/// ```
+/// Optional arguments:
+/// - `command`: the playground command that runs the snippet. Defaults to `play`
+/// - `public`: whether other users can find the snippet too. Defaults to `false`
+/// - `shared`: save into the shared namespace. Only available to moderators
+/// - any flags of the command, like `edition=2021` or `mode=release`
+#[poise::command(prefix_command, rename = "save", track_edits)]
+pub async fn snippet_save(
+	ctx: Context<'_>,
+	name: String,
+	mut flags: poise::KeyValueArgs,
+	code: Option<poise::CodeBlock>,
+) -> Result<(), Error> {
+	let name = check_name(&name)?;
+	let code = code_block_or_attachment(ctx, code, 0).await?;
+
+	let command = flags
+		.0
+		.remove("command")
+		.unwrap_or_else(|| "play".to_owned());
+	if !SNIPPET_COMMANDS.contains(&command.as_str()) {
+		bail!(
+			"Snippets can't be run with `{}`. Possible values: {}",
+			command,
+			SNIPPET_COMMANDS.join(", ")
+		);
+	}
+	let public = pop_bool(&mut flags, "public")?;
+	let shared = pop_bool(&mut flags, "shared")?;
+	if shared && !crate::checks::is_moderator(ctx) {
+		bail!("Only moderators can save shared snippets");
+	}
+
+	let (_, flag_parse_errors) = crate::commands::playground::parse_flags(flags.clone());
+	if !flag_parse_errors.is_empty() {
+		bail!("{}", flag_parse_errors.trim_end());
+	}
+
+	let owner_id = if shared {
+		SHARED_OWNER
+	} else {
+		ctx.author().id.get() as i64
+	};
+	sqlx::query(
+		"INSERT INTO snippets (owner_id, name, command, flags, code, public, updated_at)
+		VALUES ($1, $2, $3, $4, $5, $6, $7)
+		ON CONFLICT (owner_id, name) DO UPDATE SET command = EXCLUDED.command,
+			flags = EXCLUDED.flags, code = EXCLUDED.code, public = EXCLUDED.public,
+			updated_at = EXCLUDED.updated_at",
+	)
+	.bind(owner_id)
+	.bind(&name)
+	.bind(&command)
+	.bind(flags_to_string(&flags))
+	.bind(&*code)
+	.bind(public || shared)
+	.bind(unix_now())
+	.execute(&ctx.data().database)
+	.await?;
+
+	ctx.say(format!(
+		"Saved snippet `{name}`. Run it with `?snippet run {name}`"
+	))
+	.await?;
+	Ok(())
+}
+
+/// Run a saved code snippet
+///
+/// Flags given here override the flags the snippet was saved with.
+/// ```
+/// ?snippet run <name> $($flags )*
+/// ```
+#[poise::command(prefix_command, rename = "run", track_edits)]
+pub async fn snippet_run(
+	ctx: Context<'_>,
+	name: String,
+	flags: poise::KeyValueArgs,
+) -> Result<(), Error> {
+	let snippet = find_snippet(ctx, &name).await?;
+	let flags = merge_flags(&snippet.flags, flags);
+	crate::commands::playground::rerun_playground(ctx, &snippet.command, flags, &snippet.code).await
+}
+
+/// Show the code of a saved snippet
+#[poise::command(prefix_command, rename = "show", track_edits)]
+pub async fn snippet_show(ctx: Context<'_>, name: String) -> Result<(), Error> {
+	let snippet = find_snippet(ctx, &name).await?;
+
+	let mut header = format!(
+		"**{}** ({}), run with `?{}",
+		snippet.name,
+		snippet.owner(),
+		snippet.command
+	);
+	if !snippet.flags.is_empty() {
+		header += " ";
+		header += &snippet.flags;
+	}
+	header += "`\n";
+
+	let output = LongOutput {
+		header: &header,
+		blocks: vec![("rust", snippet.code.as_str())],
+		footer: "",
+	};
+	let mut reply = send_long_output(
+		ctx,
+		&output,
+		OutputMode::Auto,
+		async { None },
+		vec![],
+		vec![],
+	)
+	.await?;
+	// Without extra buttons, this only handles the page buttons until they time out
+	reply.next_interaction(ctx).await?;
+	Ok(())
+}
+
+/// List saved snippets
+///
+/// Lists your own and the shared snippets by default.
+/// ```
+/// ?snippet list [mine|shared|public]
+/// ```
+#[poise::command(prefix_command, rename = "list", track_edits)]
+pub async fn snippet_list(ctx: Context<'_>, filter: Option<String>) -> Result<(), Error> {
+	let author = ctx.author().id.get() as i64;
+	let filter = filter.unwrap_or_else(|| "default".to_owned());
+	if !["default", "mine", "shared", "public"].contains(&filter.as_str()) {
+		bail!(
+			"invalid filter `{}`. Possible values: mine, shared, public",
+			filter
+		);
+	}
+	let snippets = sqlx::query_as::<_, (i64, String, String, bool)>(
+		"SELECT owner_id, name, command, public FROM snippets
+		WHERE ($3 = 'default' AND (owner_id = $1 OR owner_id = $2))
+			OR ($3 = 'mine' AND owner_id = $1)
+			OR ($3 = 'shared' AND owner_id = $2)
+			OR ($3 = 'public' AND public)
+		ORDER BY owner_id = $2 DESC, name LIMIT $4",
+	)
+	.bind(author)
+	.bind(SHARED_OWNER)
+	.bind(&filter)
+	.bind(LIST_LENGTH)
+	.fetch_all(&ctx.data().database)
+	.await?;
+
+	if snippets.is_empty() {
+		ctx.say("No snippets found. Save one with `?snippet save <name>`")
+			.await?;
+		return Ok(());
+	}
+
+	let mut text = String::new();
+	for (owner_id, name, command, public) in snippets {
+		let _ = write!(text, "- `{name}` ?{command}");
+		if owner_id == SHARED_OWNER {
+			text += " (shared)";
+		} else if owner_id != author {
+			let _ = write!(text, " (by <@{owner_id}>)");
+		} else if public {
+			text += " (public)";
+		}
+		text += "\n";
+	}
+	ctx.say(text).await?;
+	Ok(())
+}
+
+/// Delete a saved snippet
+///
+/// Moderators can delete shared snippets with `shared=true`, and the public snippets of other \
+/// users with `owner=<user id>`.
+#[poise::command(prefix_command, rename = "delete", track_edits)]
+pub async fn snippet_delete(
+	ctx: Context<'_>,
+	name: String,
+	mut flags: poise::KeyValueArgs,
+) -> Result<(), Error> {
+	let name = check_name(&name)?;
+	let shared = pop_bool(&mut flags, "shared")?;
+	let owner = flags
+		.0
+		.remove("owner")
+		.map(|owner| owner.parse::<i64>())
+		.transpose()?;
+
+	let owner_id = match (shared, owner) {
+		(false, None) => ctx.author().id.get() as i64,
+		(true, _) | (false, Some(_)) if !crate::checks::is_moderator(ctx) => {
+			bail!("Only moderators can delete snippets of others")
+		}
+		(true, _) => SHARED_OWNER,
+		(false, Some(owner)) => owner,
+	};
+
+	// The private snippets of other users stay private, also to moderators
+	let only_public = owner.is_some() && !shared;
+	let deleted = sqlx::query(
+		"DELETE FROM snippets WHERE owner_id = $1 AND name = $2 AND (public OR NOT $3)",
+	)
+	.bind(owner_id)
+	.bind(&name)
+	.bind(only_public)
+	.execute(&ctx.data().database)
+	.await?
+	.rows_affected();
+	if deleted == 0 {
+		bail!(
+			"There's no {}snippet called `{}`",
+			if only_public { "public " } else { "" },
+			name
+		);
+	}
+
+	ctx.say(format!("Deleted snippet `{name}`")).await?;
+	Ok(())
+}
@@ -1,8 +1,10 @@
-use anyhow::Error;
+use std::fmt::Write as _;
+
+use anyhow::{anyhow, bail, Error};
 use poise::serenity_prelude as serenity;
 use tracing::warn;
 
-use crate::types::{Context, Data};
+use crate::types::{ApplicationContext, Context, Data};
 
 /// Used for playground stdout + stderr, or godbolt asm + stderr
 /// If the return value is empty, returns " " instead, because Discord displays those better in
@@ -151,3 +153,477 @@ pub async fn reply_potentially_long_text(
 		.await?;
 	Ok(())
 }
+
+/// Returns the code from the code block if there is one. Otherwise, the code is taken from the
+/// `index`th `.rs` or `.txt` file attached to the invoking message, so that code that doesn't fit
+/// into a Discord message can still be used.
+///
+/// Fails with [`poise::CodeBlockError`] if there's neither.
+pub async fn code_block_or_attachment(
+	ctx: Context<'_>,
+	code_block: Option<poise::CodeBlock>,
+	index: usize,
+) -> Result<String, Error> {
+	if let Some(code_block) = code_block {
+		return Ok(code_block.code);
+	}
+
+	let Context::Prefix(prefix_context) = ctx else {
+		return Err(poise::CodeBlockError::default().into());
+	};
+	attached_code(prefix_context.msg, index)
+		.await?
+		.ok_or_else(|| poise::CodeBlockError::default().into())
+}
+
+/// Combines a prefix command and a slash command of the same name, whose parameters differ, into
+/// one command
+#[must_use]
+pub fn with_slash_variant(
+	prefix: poise::Command<Data, Error>,
+	slash: poise::Command<Data, Error>,
+) -> poise::Command<Data, Error> {
+	poise::Command {
+		slash_action: slash.slash_action,
+		parameters: slash.parameters,
+		..prefix
+	}
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Rust code"]
+struct CodeModal {
+	#[name = "Code"]
+	#[placeholder = "fn main() {\n    println!(\"Hello, world!\");\n}"]
+	#[paragraph]
+	code: String,
+}
+
+/// Slash command options are single-line, so code is entered in a modal instead. Returns `None`
+/// if the modal was dismissed
+pub async fn code_from_modal(ctx: ApplicationContext<'_>) -> Result<Option<String>, Error> {
+	Ok(<CodeModal as poise::Modal>::execute(ctx)
+		.await?
+		.map(|modal| modal.code))
+}
+
+/// Downloads the `index`th `.rs` or `.txt` file attached to the message, if there is one
+pub async fn attached_code(
+	message: &serenity::Message,
+	index: usize,
+) -> Result<Option<String>, Error> {
+	const MAX_ATTACHMENT_SIZE: u32 = 64 * 1024;
+
+	let Some(attachment) = message
+		.attachments
+		.iter()
+		.filter(|attachment| {
+			std::path::Path::new(&attachment.filename)
+				.extension()
+				.is_some_and(|extension| {
+					extension.eq_ignore_ascii_case("rs") || extension.eq_ignore_ascii_case("txt")
+				})
+		})
+		.nth(index)
+	else {
+		return Ok(None);
+	};
+
+	if attachment.size > MAX_ATTACHMENT_SIZE {
+		bail!(
+			"`{}` is too large ({} bytes). Attached code can be at most {} bytes",
+			attachment.filename,
+			attachment.size,
+			MAX_ATTACHMENT_SIZE
+		);
+	}
+
+	String::from_utf8(attachment.download().await?)
+		.map(Some)
+		.map_err(|_| anyhow!("`{}` is not a valid UTF-8 text file", attachment.filename))
+}
+
+/// Discord's message length limit
+const MAX_MESSAGE_LENGTH: usize = 2000;
+/// More lines than this are hard to scroll past, even if they fit into a message
+const MAX_MESSAGE_LINES: usize = 45;
+/// Outputs that would need more pages than this are attached as a file instead
+const MAX_PAGES: usize = 10;
+
+/// How to present output that doesn't fit into a single message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+	/// Paginate or attach depending on the size of the output
+	#[default]
+	Auto,
+	/// Cut the output short and link to the full output
+	Truncate,
+	/// Attach the full output as a text file
+	File,
+	/// Split the output over multiple pages, with buttons to flip through them
+	Pages,
+}
+
+impl std::str::FromStr for OutputMode {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"auto" => Ok(Self::Auto),
+			"truncate" => Ok(Self::Truncate),
+			"file" => Ok(Self::File),
+			"pages" => Ok(Self::Pages),
+			_ => bail!("invalid output mode `{}`", s),
+		}
+	}
+}
+
+impl OutputMode {
+	#[must_use]
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Self::Auto => "auto",
+			Self::Truncate => "truncate",
+			Self::File => "file",
+			Self::Pages => "pages",
+		}
+	}
+}
+
+/// Command output made up of code blocks, which may be too long for a single message
+pub struct LongOutput<'a> {
+	/// Text in front of the code blocks, e.g. flag parse errors
+	pub header: &'a str,
+	/// Pairs of code block language and code block contents
+	pub blocks: Vec<(&'a str, &'a str)>,
+	/// Text after the code blocks, e.g. notes
+	pub footer: &'a str,
+}
+
+impl LongOutput<'_> {
+	fn render<'b>(&self, blocks: impl IntoIterator<Item = (&'b str, &'b str)>) -> String {
+		Self::render_with(self.header, blocks, self.footer)
+	}
+
+	fn render_with<'b>(
+		header: &str,
+		blocks: impl IntoIterator<Item = (&'b str, &'b str)>,
+		footer: &str,
+	) -> String {
+		let mut text = header.to_owned();
+		for (lang, content) in blocks {
+			let _ = write!(text, "```{lang}\n{}\n```", content.trim_end_matches('\n'));
+		}
+		text += footer;
+		text
+	}
+
+	/// Renders one of the pages of [`Self::paginate`]. The header is only on the first page and the
+	/// footer only on the last one
+	fn render_page(&self, pages: &[Vec<(&str, String)>], index: usize) -> String {
+		Self::render_with(
+			if index == 0 { self.header } else { "" },
+			pages[index]
+				.iter()
+				.map(|(lang, text)| (*lang, text.as_str())),
+			if index + 1 == pages.len() {
+				self.footer
+			} else {
+				""
+			},
+		)
+	}
+
+	fn fits_into_message(&self) -> bool {
+		self.render(self.blocks.iter().copied()).len() <= MAX_MESSAGE_LENGTH
+			&& self
+				.blocks
+				.iter()
+				.map(|(_, content)| content.lines().count())
+				.sum::<usize>()
+				<= MAX_MESSAGE_LINES
+	}
+
+	/// Splits the code blocks into pages that each fit into a message. The header goes on the first
+	/// page and the footer on the last one, on pages of their own if they're too long to share one
+	/// with code. Every page is a list of code blocks again
+	fn paginate(&self) -> Vec<Vec<(&str, String)>> {
+		// Some room is left for the code block fences
+		let max_len = MAX_MESSAGE_LENGTH - 100;
+		let first_page_len = self.header.len();
+
+		let mut pages = Vec::new();
+		let mut page: Vec<(&str, String)> = Vec::new();
+		let (mut page_len, mut page_lines) = (first_page_len, 0);
+		for &(lang, content) in &self.blocks {
+			let lines = content
+				.lines()
+				.flat_map(|line| split_at_char_boundaries(line, max_len / 2));
+			for line in lines {
+				let continues_block = matches!(page.last(), Some((l, _)) if *l == lang);
+				let block_overhead = if continues_block { 0 } else { lang.len() + 8 };
+				// A page always gets at least one line, except for the first one, where a long
+				// header may not leave room for any
+				let is_header_only = pages.is_empty() && page_len == first_page_len;
+				if (!page.is_empty() || is_header_only && first_page_len > 0)
+					&& (page_len + block_overhead + line.len() + 1 > max_len
+						|| page_lines == MAX_MESSAGE_LINES)
+				{
+					pages.push(std::mem::take(&mut page));
+					page_len = 0;
+					page_lines = 0;
+				}
+
+				if !matches!(page.last(), Some((l, _)) if *l == lang) {
+					page.push((lang, String::new()));
+					page_len += lang.len() + 8;
+				}
+				if let Some((_, text)) = page.last_mut() {
+					text.push_str(line);
+					text.push('\n');
+				}
+				page_len += line.len() + 1;
+				page_lines += 1;
+			}
+		}
+		if !page.is_empty() {
+			pages.push(page);
+		}
+		if page_len + self.footer.len() > max_len {
+			pages.push(Vec::new());
+		}
+		pages
+	}
+
+	fn file_contents(&self) -> String {
+		self.blocks
+			.iter()
+			.map(|(_, content)| content.trim_end())
+			.collect::<Vec<_>>()
+			.join("\n\n")
+	}
+}
+
+/// Splits a line into pieces of at most `max_len` bytes
+fn split_at_char_boundaries(mut line: &str, max_len: usize) -> Vec<&str> {
+	let mut pieces = Vec::new();
+	while line.len() > max_len {
+		let mut split_pos = max_len;
+		while !line.is_char_boundary(split_pos) {
+			split_pos -= 1;
+		}
+		let (piece, rest) = line.split_at(split_pos);
+		pieces.push(piece);
+		line = rest;
+	}
+	pieces.push(line);
+	pieces
+}
+
+fn page_buttons(
+	custom_id_prefix: &str,
+	page: usize,
+	page_count: usize,
+) -> serenity::CreateActionRow {
+	serenity::CreateActionRow::Buttons(vec![
+		serenity::CreateButton::new(format!("{custom_id_prefix}-prev"))
+			.label("Prev")
+			.style(serenity::ButtonStyle::Secondary)
+			.disabled(page == 0),
+		serenity::CreateButton::new(format!("{custom_id_prefix}-page"))
+			.label(format!("{}/{}", page + 1, page_count))
+			.style(serenity::ButtonStyle::Secondary)
+			.disabled(true),
+		serenity::CreateButton::new(format!("{custom_id_prefix}-next"))
+			.label("Next")
+			.style(serenity::ButtonStyle::Secondary)
+			.disabled(page + 1 == page_count),
+	])
+}
+
+/// Replies with output that may not fit into a single message. Depending on `mode`, oversized
+/// output is paginated, attached as a file, or truncated with a note that links to the full
+/// output.
+///
+/// `truncation_msg` is only awaited when truncating. If it fails to produce a note, the output is
+/// attached as a file instead.
+///
+/// `extra_components` are added below the output. Their custom IDs must start with the invocation
+/// ID (`ctx.id()`), so that [`LongOutputReply::next_interaction`] picks them up. `attachments` are
+/// attached to the reply in any case, e.g. images that go along with the output.
+pub async fn send_long_output(
+	ctx: Context<'_>,
+	output: &LongOutput<'_>,
+	mode: OutputMode,
+	truncation_msg: impl std::future::Future<Output = Option<String>>,
+	extra_components: Vec<serenity::CreateActionRow>,
+	attachments: Vec<serenity::CreateAttachment>,
+) -> Result<LongOutputReply, Error> {
+	let mut pages = Vec::new();
+	let mut reply = if output.fits_into_message() {
+		poise::CreateReply::default().content(output.render(output.blocks.iter().copied()))
+	} else {
+		match mode {
+			OutputMode::Auto | OutputMode::Pages => {
+				let blocks = output.paginate();
+				pages = (0..blocks.len())
+					.map(|index| output.render_page(&blocks, index))
+					.collect::<Vec<_>>();
+				if mode == OutputMode::Auto && pages.len() > MAX_PAGES {
+					pages.clear();
+					file_reply(output)
+				} else {
+					poise::CreateReply::default().content(pages[0].clone())
+				}
+			}
+			OutputMode::Truncate => match truncation_msg.await {
+				Some(truncation_msg) => poise::CreateReply::default().content(
+					trim_text(
+						&format!(
+							"{}```{}\n{}",
+							output.header,
+							output.blocks.first().map_or("", |(lang, _)| *lang),
+							output.file_contents()
+						),
+						&format!("```{}", output.footer),
+						async { truncation_msg },
+					)
+					.await,
+				),
+				None => file_reply(output),
+			},
+			OutputMode::File => file_reply(output),
+		}
+	};
+
+	for attachment in attachments {
+		reply = reply.attachment(attachment);
+	}
+
+	let mut reply_handle = LongOutputReply {
+		message: None,
+		custom_id_prefix: ctx.id().to_string(),
+		pages,
+		page: 0,
+		extra_components,
+	};
+	let response = ctx
+		.send(reply.components(reply_handle.components()))
+		.await?;
+	if !reply_handle.components().is_empty() {
+		reply_handle.message = Some(response.message().await?.into_owned());
+	}
+	Ok(reply_handle)
+}
+
+/// A reply sent by [`send_long_output`]
+pub struct LongOutputReply {
+	/// Only set if there are buttons below the reply
+	message: Option<serenity::Message>,
+	custom_id_prefix: String,
+	/// Empty if the output isn't paginated
+	pages: Vec<String>,
+	page: usize,
+	extra_components: Vec<serenity::CreateActionRow>,
+}
+
+impl LongOutputReply {
+	fn components(&self) -> Vec<serenity::CreateActionRow> {
+		let mut components = Vec::new();
+		if self.pages.len() > 1 {
+			components.push(page_buttons(
+				&self.custom_id_prefix,
+				self.page,
+				self.pages.len(),
+			));
+		}
+		components.extend(self.extra_components.iter().cloned());
+		components
+	}
+
+	/// Waits for a press of one of the `extra_components` and returns it. Page buttons are
+	/// handled in the meantime.
+	///
+	/// Returns `None` once no button was pressed for ten minutes, in which case the buttons are
+	/// removed from the reply.
+	pub async fn next_interaction(
+		&mut self,
+		ctx: Context<'_>,
+	) -> Result<Option<serenity::ComponentInteraction>, Error> {
+		let Some(message) = &self.message else {
+			return Ok(None);
+		};
+
+		loop {
+			let custom_id_prefix = self.custom_id_prefix.clone();
+			let Some(interaction) = message
+				.await_component_interaction(ctx)
+				.filter(move |mci: &serenity::ComponentInteraction| {
+					mci.data.custom_id.starts_with(&custom_id_prefix)
+				})
+				.timeout(std::time::Duration::from_mins(10))
+				.await
+			else {
+				break;
+			};
+
+			self.page = match interaction.data.custom_id.rsplit('-').next() {
+				Some("prev") => self.page.saturating_sub(1),
+				Some("next") => (self.page + 1).min(self.pages.len() - 1),
+				_ => return Ok(Some(interaction)),
+			};
+			interaction
+				.create_response(
+					ctx,
+					serenity::CreateInteractionResponse::UpdateMessage(
+						serenity::CreateInteractionResponseMessage::new()
+							.content(self.pages[self.page].clone())
+							.components(self.components()),
+					),
+				)
+				.await?;
+		}
+
+		self.remove_buttons(ctx).await;
+		Ok(None)
+	}
+
+	/// Errors are ignored in case the reply was deleted
+	pub async fn remove_buttons(&mut self, ctx: Context<'_>) {
+		if let Some(message) = &mut self.message {
+			let _: Result<_, _> = message
+				.edit(ctx, serenity::EditMessage::new().components(vec![]))
+				.await;
+		}
+	}
+}
+
+/// Builds a standalone message for the output, e.g. to reply to another message. Output that
+/// doesn't fit is attached as a file
+pub fn long_output_message(output: &LongOutput<'_>) -> serenity::CreateMessage {
+	if output.fits_into_message() {
+		serenity::CreateMessage::new().content(output.render(output.blocks.iter().copied()))
+	} else {
+		serenity::CreateMessage::new()
+			.content(format!(
+				"{}Output too large, see the attached file.{}",
+				output.header, output.footer
+			))
+			.add_file(serenity::CreateAttachment::bytes(
+				output.file_contents().into_bytes(),
+				"output.txt",
+			))
+	}
+}
+
+fn file_reply(output: &LongOutput<'_>) -> poise::CreateReply {
+	poise::CreateReply::default()
+		.content(format!(
+			"{}Output too large, see the attached file.{}",
+			output.header, output.footer
+		))
+		.attachment(serenity::CreateAttachment::bytes(
+			output.file_contents().into_bytes(),
+			"output.txt",
+		))
+}
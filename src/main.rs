@@ -21,9 +21,11 @@ use shuttle_runtime::SecretStore;
 use shuttle_serenity::ShuttleSerenity;
 use tracing::{debug, info, warn};
 
+pub mod cache;
 pub mod checks;
 pub mod commands;
 pub mod helpers;
+pub mod scheduler;
 pub mod types;
 
 #[shuttle_runtime::main]
@@ -39,7 +41,8 @@ or
 `\x1b[0m`\x1b[0m`rust
 code here
 `\x1b[0m`\x1b[0m`
-```";
+```
+You can also attach the code as a `.rs` or `.txt` file.";
 
 	let token = secret_store
 		.get("DISCORD_TOKEN")
@@ -76,9 +79,18 @@ code here
 			commands: vec![
 				commands::crates::crate_(),
 				commands::crates::doc(),
-				commands::godbolt::godbolt(),
-				commands::godbolt::mca(),
-				commands::godbolt::llvmir(),
+				helpers::with_slash_variant(
+					commands::godbolt::godbolt(),
+					commands::godbolt::godbolt_slash(),
+				),
+				helpers::with_slash_variant(
+					commands::godbolt::mca(),
+					commands::godbolt::mca_slash(),
+				),
+				helpers::with_slash_variant(
+					commands::godbolt::llvmir(),
+					commands::godbolt::llvmir_slash(),
+				),
 				commands::godbolt::targets(),
 				commands::utilities::go(),
 				commands::utilities::source(),
@@ -93,15 +105,53 @@ code here
 				commands::modmail::modmail(),
 				commands::modmail::modmail_context_menu_for_message(),
 				commands::modmail::modmail_context_menu_for_user(),
-				commands::playground::play(),
+				helpers::with_slash_variant(
+					commands::playground::play(),
+					commands::playground::with_flag_choices(commands::playground::play_slash()),
+				),
 				commands::playground::playwarn(),
-				commands::playground::eval(),
-				commands::playground::miri(),
-				commands::playground::expand(),
-				commands::playground::clippy(),
-				commands::playground::fmt(),
-				commands::playground::microbench(),
-				commands::playground::procmacro(),
+				helpers::with_slash_variant(
+					commands::playground::eval(),
+					commands::playground::with_flag_choices(commands::playground::eval_slash()),
+				),
+				commands::playground::repl(),
+				commands::playground::playall(),
+				commands::playground::test(),
+				helpers::with_slash_variant(
+					commands::playground::miri(),
+					commands::playground::with_flag_choices(commands::playground::miri_slash()),
+				),
+				helpers::with_slash_variant(
+					commands::playground::expand(),
+					commands::playground::with_flag_choices(commands::playground::expand_slash()),
+				),
+				helpers::with_slash_variant(
+					commands::playground::clippy(),
+					commands::playground::with_flag_choices(commands::playground::clippy_slash()),
+				),
+				helpers::with_slash_variant(
+					commands::playground::fmt(),
+					commands::playground::with_flag_choices(commands::playground::fmt_slash()),
+				),
+				helpers::with_slash_variant(
+					commands::playground::microbench(),
+					commands::playground::with_flag_choices(
+						commands::playground::microbench_slash(),
+					),
+				),
+				helpers::with_slash_variant(
+					commands::playground::procmacro(),
+					commands::playground::with_flag_choices(commands::playground::procmacro_slash()),
+				),
+				commands::playground::mir(),
+				commands::playground::hir(),
+				commands::playground::ir(),
+				commands::playground::asm(),
+				commands::playground::wasm(),
+				commands::history::history(),
+				commands::history::rerun(),
+				commands::snippet::snippet(),
+				commands::code_actions::code_actions(),
 			],
 			prefix_options: poise::PrefixFrameworkOptions {
 				prefix: Some("?".into()),
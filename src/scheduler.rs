@@ -0,0 +1,363 @@
+//! Limits how much code is run on the external services at once
+//!
+//! Every playground or Godbolt request has to wait for a free slot of its service first. Waiting
+//! requests are queued fairly: a user's second request only goes after everyone else's first one.
+//! On top of that, users have a cooldown between requests and a limit on how many of their requests
+//! may be running or queued at the same time, so that a single user can't get the bot's IP
+//! rate-limited.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Error};
+use poise::serenity_prelude as serenity;
+use shuttle_runtime::SecretStore;
+use tokio::sync::oneshot;
+
+use crate::types::Context;
+
+/// How often the queue position in the stub message is updated
+const POSITION_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+	Playground,
+	Godbolt,
+}
+
+impl Service {
+	#[must_use]
+	pub fn name(self) -> &'static str {
+		match self {
+			Service::Playground => "playground",
+			Service::Godbolt => "Godbolt",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+	/// Minimum time between two requests of the same user
+	pub cooldown: Duration,
+	/// How many requests of a single user may be running or queued at once, per service
+	pub max_per_user: usize,
+	/// How many playground requests may run at once
+	pub playground_slots: usize,
+	/// How many Godbolt requests may run at once
+	pub godbolt_slots: usize,
+}
+
+impl SchedulerConfig {
+	pub fn from_secrets(secret_store: &SecretStore) -> Result<Self, Error> {
+		let number = |key: &str, default: u64| -> Result<u64, Error> {
+			Ok(secret_store
+				.get(key)
+				.map(|s| s.parse::<u64>())
+				.transpose()?
+				.unwrap_or(default))
+		};
+
+		Ok(Self {
+			cooldown: Duration::from_millis(number("SCHEDULER_COOLDOWN_MS", 3000)?),
+			max_per_user: usize::try_from(number("SCHEDULER_MAX_PER_USER", 2)?)?,
+			playground_slots: usize::try_from(number("SCHEDULER_PLAYGROUND_SLOTS", 4)?)?,
+			godbolt_slots: usize::try_from(number("SCHEDULER_GODBOLT_SLOTS", 2)?)?,
+		})
+	}
+
+	fn slots(&self, service: Service) -> usize {
+		match service {
+			Service::Playground => self.playground_slots,
+			Service::Godbolt => self.godbolt_slots,
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Waiter {
+	id: u64,
+	user: serenity::UserId,
+	/// How many requests of the same user were running or queued before this one
+	round: usize,
+	ready: oneshot::Sender<Permit>,
+}
+
+#[derive(Debug, Default)]
+struct ServiceState {
+	/// Users of the running requests, one entry per request
+	running: Vec<serenity::UserId>,
+	/// Sorted by round, then by arrival
+	queue: Vec<Waiter>,
+}
+
+impl ServiceState {
+	fn requests_of(&self, user: serenity::UserId) -> usize {
+		self.running.iter().filter(|&&u| u == user).count()
+			+ self.queue.iter().filter(|w| w.user == user).count()
+	}
+}
+
+/// The last request of a user, for the cooldown
+#[derive(Debug, Clone, Copy)]
+struct LastRequest {
+	at: Instant,
+	/// ID of the command invocation that made the request
+	invocation: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	services: HashMap<Service, ServiceState>,
+	/// Only users whose cooldown hasn't run out yet
+	last_request: HashMap<serenity::UserId, LastRequest>,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+	config: SchedulerConfig,
+	state: Arc<Mutex<State>>,
+	next_id: AtomicU64,
+}
+
+impl Scheduler {
+	#[must_use]
+	pub fn new(config: SchedulerConfig) -> Self {
+		Self {
+			config,
+			state: Arc::default(),
+			next_id: AtomicU64::new(0),
+		}
+	}
+
+	/// Takes a free slot of `service` for another request of `user` right away, if there's one
+	/// that nobody is waiting for. For invocations that already hold a permit and would deadlock
+	/// if they queued for more, like ?miri with several seeds
+	pub fn try_acquire(&self, user: serenity::UserId, service: Service) -> Option<Permit> {
+		let mut state = self.state.lock().unwrap();
+		let slots = self.config.slots(service);
+		let service_state = state.services.entry(service).or_default();
+		if service_state.running.len() >= slots || !service_state.queue.is_empty() {
+			return None;
+		}
+		service_state.running.push(user);
+		Some(Permit {
+			state: self.state.clone(),
+			service,
+			user,
+			slots,
+		})
+	}
+
+	/// Puts a request of `user` into the queue of `service`. Fails if the user is on cooldown or
+	/// has too many requests already. Further requests of the same invocation, like the ones of
+	/// the rerun buttons, aren't subject to the cooldown
+	fn enqueue(
+		&self,
+		user: serenity::UserId,
+		invocation: u64,
+		service: Service,
+	) -> Result<Ticket, Error> {
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let (sender, receiver) = oneshot::channel();
+
+		let failed = {
+			let mut state = self.state.lock().unwrap();
+			let now = Instant::now();
+			let cooldown = self.config.cooldown;
+			state
+				.last_request
+				.retain(|_, last_request| now.duration_since(last_request.at) < cooldown);
+			if let Some(last_request) = state.last_request.get(&user) {
+				if last_request.invocation != invocation {
+					bail!(
+						"Please wait {:.1}s before running more code",
+						cooldown
+							.saturating_sub(now.duration_since(last_request.at))
+							.as_secs_f32()
+					);
+				}
+			}
+
+			let service_state = state.services.entry(service).or_default();
+			let round = service_state.requests_of(user);
+			if round >= self.config.max_per_user {
+				bail!(
+					"You already have {} requests running or queued on the {}, please wait for \
+					them to finish",
+					round,
+					service.name()
+				);
+			}
+
+			let index = service_state
+				.queue
+				.iter()
+				.position(|w| w.round > round)
+				.unwrap_or(service_state.queue.len());
+			service_state.queue.insert(
+				index,
+				Waiter {
+					id,
+					user,
+					round,
+					ready: sender,
+				},
+			);
+			state.last_request.insert(
+				user,
+				LastRequest {
+					at: now,
+					invocation,
+				},
+			);
+
+			dispatch(&self.state, &mut state, service, self.config.slots(service))
+		};
+		drop(failed);
+
+		Ok(Ticket {
+			state: self.state.clone(),
+			service,
+			id,
+			ready: receiver,
+		})
+	}
+}
+
+/// Hands out permits to the front of the queue while there are free slots. Returns the permits
+/// whose waiters went away, which must be dropped after unlocking the state
+fn dispatch(
+	state_handle: &Arc<Mutex<State>>,
+	state: &mut State,
+	service: Service,
+	slots: usize,
+) -> Vec<Permit> {
+	let mut failed = Vec::new();
+	let service_state = state.services.entry(service).or_default();
+	while service_state.running.len() < slots && !service_state.queue.is_empty() {
+		let waiter = service_state.queue.remove(0);
+		service_state.running.push(waiter.user);
+		let permit = Permit {
+			state: state_handle.clone(),
+			service,
+			user: waiter.user,
+			slots,
+		};
+		if let Err(permit) = waiter.ready.send(permit) {
+			failed.push(permit);
+		}
+	}
+	failed
+}
+
+/// A request waiting in the queue. Dropping it leaves the queue
+struct Ticket {
+	state: Arc<Mutex<State>>,
+	service: Service,
+	id: u64,
+	ready: oneshot::Receiver<Permit>,
+}
+
+impl Ticket {
+	/// Number of requests in front of this one, or `None` if it's not queued anymore
+	fn position(&self) -> Option<usize> {
+		let state = self.state.lock().unwrap();
+		state
+			.services
+			.get(&self.service)?
+			.queue
+			.iter()
+			.position(|w| w.id == self.id)
+	}
+}
+
+impl Drop for Ticket {
+	fn drop(&mut self) {
+		let mut state = self.state.lock().unwrap();
+		if let Some(service_state) = state.services.get_mut(&self.service) {
+			service_state.queue.retain(|w| w.id != self.id);
+		}
+	}
+}
+
+/// Allows running a request. The slot is freed when this is dropped
+#[derive(Debug)]
+pub struct Permit {
+	state: Arc<Mutex<State>>,
+	service: Service,
+	user: serenity::UserId,
+	slots: usize,
+}
+
+impl Drop for Permit {
+	fn drop(&mut self) {
+		let failed = {
+			let mut state = self.state.lock().unwrap();
+			if let Some(service_state) = state.services.get_mut(&self.service) {
+				if let Some(index) = service_state.running.iter().position(|&u| u == self.user) {
+					service_state.running.remove(index);
+				}
+			}
+			dispatch(&self.state, &mut state, self.service, self.slots)
+		};
+		drop(failed);
+	}
+}
+
+/// Waits for a free slot of `service` to run a request of the command invoker. While waiting, the
+/// queue position is shown as the command's response
+///
+/// The slot is held until the returned permit is dropped.
+pub async fn wait_for_turn(ctx: Context<'_>, service: Service) -> Result<Permit, Error> {
+	let mut ticket = ctx
+		.data()
+		.scheduler
+		.enqueue(ctx.author().id, ctx.id(), service)?;
+
+	let mut status_message: Option<poise::ReplyHandle<'_>> = None;
+	let mut shown_position = None;
+	let permit = loop {
+		if let Some(position) = ticket.position() {
+			if shown_position != Some(position) {
+				shown_position = Some(position);
+				let text = format!(
+					"_Waiting for a free {} slot, position {} in queue..._",
+					service.name(),
+					position + 1
+				);
+				match &status_message {
+					Some(handle) => {
+						handle
+							.edit(ctx, poise::CreateReply::default().content(text))
+							.await?;
+					}
+					None => status_message = Some(ctx.say(text).await?),
+				}
+			}
+		}
+
+		tokio::select! {
+			permit = &mut ticket.ready => {
+				break permit.map_err(|_| anyhow!("The request queue was shut down"))?;
+			}
+			() = tokio::time::sleep(POSITION_UPDATE_INTERVAL) => {}
+		}
+	};
+
+	if let Some(handle) = status_message {
+		if let Context::Application(_) = ctx {
+			// In application commands, the status message is a separate message. Errors are
+			// ignored in case it was deleted already
+			let _: Result<_, _> = handle.delete(ctx).await;
+		} else {
+			let text = format!("_Running code on {}..._", service.name());
+			handle
+				.edit(ctx, poise::CreateReply::default().content(text))
+				.await?;
+		}
+	}
+
+	Ok(permit)
+}
@@ -18,11 +18,21 @@ pub struct Data {
 	pub bot_start_time: std::time::Instant,
 	pub http: reqwest::Client,
 	pub godbolt_metadata: std::sync::Mutex<commands::godbolt::GodboltMetadata>,
+	pub playground: Arc<dyn commands::playground::PlaygroundBackend>,
+	pub scheduler: crate::scheduler::Scheduler,
+	pub cache: crate::cache::ResultCache,
 }
 
 impl Data {
 	pub fn new(secret_store: &SecretStore, database: sqlx::PgPool) -> Result<Self> {
+		let http = reqwest::Client::new();
+		let playground = commands::playground::backend_from_secrets(secret_store, http.clone())?;
+
 		Ok(Self {
+			cache: crate::cache::ResultCache::new(
+				crate::cache::CacheConfig::from_secrets(secret_store)?,
+				database.clone(),
+			),
 			database,
 			discord_guild_id: secret_store
 				.get("DISCORD_GUILD")
@@ -59,13 +69,18 @@ impl Data {
 				.into(),
 			modmail_message: Arc::default(),
 			bot_start_time: std::time::Instant::now(),
-			http: reqwest::Client::new(),
+			http,
 			godbolt_metadata: std::sync::Mutex::new(commands::godbolt::GodboltMetadata::default()),
+			playground,
+			scheduler: crate::scheduler::Scheduler::new(
+				crate::scheduler::SchedulerConfig::from_secrets(secret_store)?,
+			),
 		})
 	}
 }
 
 pub type Context<'a> = poise::Context<'a, Data, Error>;
+pub type ApplicationContext<'a> = poise::ApplicationContext<'a, Data, Error>;
 
 // const EMBED_COLOR: (u8, u8, u8) = (0xf7, 0x4c, 0x00);
 pub const EMBED_COLOR: (u8, u8, u8) = (0xb7, 0x47, 0x00); // slightly less saturated